@@ -1,6 +1,7 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+  /* NOTE the top 8K of flash is reserved for storing settings (see src/flash.rs) */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 56K
   RAM : ORIGIN = 0x20000000, LENGTH = 20K
 }
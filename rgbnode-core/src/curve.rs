@@ -0,0 +1,142 @@
+
+use libm::powf;
+
+
+// Transfer curves from an 8-bit channel value to the fraction of full PWM duty
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputCurve {
+    Linear,
+    Square,
+    Cie1931,
+    // Gamma exponent in hundredths (ie. 220 is a gamma of 2.2)
+    Gamma(u16),
+}
+
+impl OutputCurve {
+    pub fn parse(args: &[&str]) -> Option<OutputCurve> {
+        match args.first() {
+            Some(&"linear") => Some(OutputCurve::Linear),
+            Some(&"square") => Some(OutputCurve::Square),
+            Some(&"cie") => Some(OutputCurve::Cie1931),
+            Some(&"gamma") if args.len() > 1 => {
+                // The gamma is checked after rounding, since a gamma of 0 would turn every value on fully
+                match lexical_core::parse::<f32>(args[1].as_bytes()) {
+                    Ok(gamma) if gamma > 0.0 && gamma <= 10.0 => match (gamma * 100.0 + 0.5) as u16 {
+                        0 => None,
+                        hundredths => Some(OutputCurve::Gamma(hundredths)),
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputCurve::Linear => "linear",
+            OutputCurve::Square => "square",
+            OutputCurve::Cie1931 => "cie",
+            OutputCurve::Gamma(_) => "gamma",
+        }
+    }
+
+    fn evaluate(&self, value: u8) -> f32 {
+        let x = value as f32 / 255.0;
+        match self {
+            OutputCurve::Linear => x,
+            OutputCurve::Square => x * x,
+            OutputCurve::Cie1931 => {
+                let lightness = x * 100.0;
+                if lightness <= 8.0 {
+                    lightness / 903.3
+                } else {
+                    powf((lightness + 16.0) / 116.0, 3.0)
+                }
+            },
+            OutputCurve::Gamma(gamma) => powf(x, *gamma as f32 / 100.0),
+        }
+    }
+}
+
+// The curve is precomputed whenever it changes, so that setting the output only needs a table lookup
+pub struct CurveTable {
+    curve: OutputCurve,
+    table: [u16; 256],
+}
+
+impl CurveTable {
+    pub fn new(curve: OutputCurve) -> Self {
+        let mut table = CurveTable {
+            curve,
+            table: [0; 256],
+        };
+        table.set_curve(curve);
+        table
+    }
+
+    pub fn curve(&self) -> OutputCurve {
+        self.curve
+    }
+
+    pub fn set_curve(&mut self, curve: OutputCurve) {
+        self.curve = curve;
+        for (i, entry) in self.table.iter_mut().enumerate() {
+            *entry = (curve.evaluate(i as u8) * u16::MAX as f32 + 0.5) as u16;
+        }
+    }
+
+    pub fn duty(&self, value: u8, max_duty: u16) -> u16 {
+        ((self.table[value as usize] as u32 * max_duty as u32) / u16::MAX as u32) as u16
+    }
+}
+
+// Scale the maximum duty cycle by a per-channel calibration factor, where 255 is full scale
+pub fn calibrated_duty(max_duty: u16, factor: u8) -> u16 {
+    ((max_duty as u32 * factor as u32) / 255) as u16
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve_endpoints() {
+        for curve in &[OutputCurve::Linear, OutputCurve::Square, OutputCurve::Cie1931, OutputCurve::Gamma(220)] {
+            let table = CurveTable::new(*curve);
+            assert_eq!(table.duty(0, 1000), 0);
+            assert_eq!(table.duty(255, 1000), 1000);
+        }
+    }
+
+    #[test]
+    fn test_curve_shape() {
+        let linear = CurveTable::new(OutputCurve::Linear);
+        let square = CurveTable::new(OutputCurve::Square);
+        let cie = CurveTable::new(OutputCurve::Cie1931);
+        assert_eq!(linear.duty(128, 1000), 501);
+        assert_eq!(square.duty(128, 1000), 251);
+        assert!(cie.duty(128, 1000) < linear.duty(128, 1000));
+        assert!(cie.duty(1, 1000) <= cie.duty(2, 1000));
+    }
+
+    #[test]
+    fn test_calibrated_duty() {
+        let table = CurveTable::new(OutputCurve::Linear);
+        assert_eq!(calibrated_duty(1000, 255), 1000);
+        assert_eq!(calibrated_duty(1000, 0), 0);
+        assert_eq!(table.duty(255, calibrated_duty(1000, 153)), 600);
+    }
+
+    #[test]
+    fn test_curve_parse() {
+        assert_eq!(OutputCurve::parse(&["cie"]), Some(OutputCurve::Cie1931));
+        assert_eq!(OutputCurve::parse(&["gamma", "2.2"]), Some(OutputCurve::Gamma(220)));
+        assert_eq!(OutputCurve::parse(&["gamma"]), None);
+        assert_eq!(OutputCurve::parse(&["gamma", "-1"]), None);
+        assert_eq!(OutputCurve::parse(&["gamma", "0.004"]), None);
+        assert_eq!(OutputCurve::parse(&["gamma", "0.01"]), Some(OutputCurve::Gamma(1)));
+        assert_eq!(OutputCurve::parse(&["bogus"]), None);
+    }
+}
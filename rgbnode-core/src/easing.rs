@@ -0,0 +1,124 @@
+
+use core::f32::consts::PI;
+
+use libm::{ cosf, exp2f };
+
+use crate::rgb::{ PROGRESS_MAX };
+
+
+// Curves that change the rate of a fade, while still starting and ending at the same colours
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Sine,
+    Exponential,
+}
+
+const EASINGS: &[(&str, Easing)] = &[
+    ("linear", Easing::Linear),
+    ("ease-in", Easing::EaseIn),
+    ("ease-out", Easing::EaseOut),
+    ("ease-in-out", Easing::EaseInOut),
+    ("sine", Easing::Sine),
+    ("exponential", Easing::Exponential),
+];
+
+impl Easing {
+    pub fn parse(name: &str) -> Option<Easing> {
+        EASINGS.iter().find(|(easing_name, _)| *easing_name == name).map(|(_, easing)| *easing)
+    }
+
+    pub fn name(&self) -> &'static str {
+        EASINGS.iter().find(|(_, easing)| easing == self).map(|(name, _)| *name).unwrap_or("linear")
+    }
+
+    // Used when saving settings
+    pub fn from_u8(value: u8) -> Option<Easing> {
+        EASINGS.get(value as usize).map(|(_, easing)| *easing)
+    }
+
+    pub fn to_u8(self) -> u8 {
+        EASINGS.iter().position(|(_, easing)| *easing == self).unwrap_or(0) as u8
+    }
+
+    // Map the fixed point progress of a fade (0 to PROGRESS_MAX) to the eased progress
+    pub fn apply(&self, progress: u32) -> u32 {
+        let progress = progress.min(PROGRESS_MAX) as u64;
+        let max = PROGRESS_MAX as u64;
+
+        let eased = match self {
+            Easing::Linear => progress,
+            Easing::EaseIn => progress * progress / max,
+            Easing::EaseOut => {
+                let remain = max - progress;
+                max - remain * remain / max
+            },
+            Easing::EaseInOut => {
+                if progress < max / 2 {
+                    2 * progress * progress / max
+                } else {
+                    let remain = max - progress;
+                    max - 2 * remain * remain / max
+                }
+            },
+            Easing::Sine => {
+                let x = progress as f32 / max as f32;
+                ((1.0 - cosf(PI * x)) / 2.0 * max as f32 + 0.5) as u64
+            },
+            Easing::Exponential => {
+                if progress == 0 {
+                    0
+                } else {
+                    let x = progress as f32 / max as f32;
+                    (exp2f(10.0 * (x - 1.0)) * max as f32 + 0.5) as u64
+                }
+            },
+        };
+        eased.min(max) as u32
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_easing_endpoints() {
+        for (_, easing) in EASINGS {
+            assert_eq!(easing.apply(0), 0);
+            assert_eq!(easing.apply(PROGRESS_MAX), PROGRESS_MAX);
+
+            let mut last = 0;
+            for i in 0..=64 {
+                let eased = easing.apply(i * PROGRESS_MAX / 64);
+                assert!(eased >= last);
+                last = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn test_easing_shape() {
+        let half = PROGRESS_MAX / 2;
+        let quarter = PROGRESS_MAX / 4;
+        assert_eq!(Easing::Linear.apply(quarter), quarter);
+        assert_eq!(Easing::EaseIn.apply(half), PROGRESS_MAX / 4);
+        assert_eq!(Easing::EaseOut.apply(half), PROGRESS_MAX * 3 / 4);
+        assert_eq!(Easing::EaseInOut.apply(half), half);
+        assert_eq!(Easing::Sine.apply(half), half);
+        assert!(Easing::Sine.apply(quarter) < quarter);
+        assert!(Easing::Exponential.apply(half) < PROGRESS_MAX / 16);
+    }
+
+    #[test]
+    fn test_easing_names() {
+        assert_eq!(Easing::parse("ease-in-out"), Some(Easing::EaseInOut));
+        assert_eq!(Easing::parse("bogus"), None);
+        assert_eq!(Easing::Sine.name(), "sine");
+        assert_eq!(Easing::from_u8(Easing::Exponential.to_u8()), Some(Easing::Exponential));
+    }
+}
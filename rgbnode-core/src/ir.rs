@@ -0,0 +1,562 @@
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{ AtomicBool, AtomicU32, AtomicUsize, Ordering };
+
+
+pub const IR_BINDINGS_MAX: usize = 32;
+pub const IR_FILTER_MAX: usize = 4;
+// One slot is always left empty, so this holds one less code than its size
+pub const IR_QUEUE_SIZE: usize = 8;
+
+// The size of the bindings when encoded for saving to flash
+pub const IR_BINDINGS_ENCODED_SIZE: usize = 2 + IR_BINDINGS_MAX * BINDING_ENCODED_SIZE;
+const BINDING_ENCODED_SIZE: usize = 5;
+const IR_BINDINGS_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IrError;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IrType {
+    Nec,
+    Samsung,
+    Rc5,
+    Rc6,
+}
+
+const IR_TYPES: &[(&str, IrType)] = &[
+    ("nec", IrType::Nec),
+    ("samsung", IrType::Samsung),
+    ("rc5", IrType::Rc5),
+    ("rc6", IrType::Rc6),
+];
+
+impl IrType {
+    pub fn parse(name: &str) -> Option<IrType> {
+        IR_TYPES.iter().find(|(type_name, _)| *type_name == name).map(|(_, protocol)| *protocol)
+    }
+
+    pub fn name(&self) -> &'static str {
+        IR_TYPES.iter().find(|(_, protocol)| protocol == self).map(|(name, _)| *name).unwrap_or("nec")
+    }
+
+    // Used when saving bindings
+    pub fn from_u8(value: u8) -> Option<IrType> {
+        IR_TYPES.get(value as usize).map(|(_, protocol)| *protocol)
+    }
+
+    pub fn to_u8(self) -> u8 {
+        IR_TYPES.iter().position(|(_, protocol)| *protocol == self).unwrap_or(0) as u8
+    }
+}
+
+// The protocols that are listened to, as a bit for each protocol in the order of IR_TYPES
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IrProtocols(u8);
+
+impl IrProtocols {
+    pub const ALL: IrProtocols = IrProtocols((1 << IR_TYPES.len()) - 1);
+    pub const NONE: IrProtocols = IrProtocols(0);
+
+    // Parse a list of protocol names, or "all"
+    pub fn parse(names: &[&str]) -> Option<IrProtocols> {
+        match names {
+            [] => None,
+            ["all"] => Some(IrProtocols::ALL),
+            names => names.iter().try_fold(IrProtocols::NONE, |protocols, name| Some(protocols.with(IrType::parse(name)?))),
+        }
+    }
+
+    pub fn with(self, protocol: IrType) -> IrProtocols {
+        IrProtocols(self.0 | 1 << protocol.to_u8())
+    }
+
+    pub fn contains(&self, protocol: IrType) -> bool {
+        self.0 & 1 << protocol.to_u8() != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = IrType> + '_ {
+        IR_TYPES.iter().map(|(_, protocol)| *protocol).filter(move |protocol| self.contains(*protocol))
+    }
+
+    // Used when saving settings
+    pub fn from_u8(value: u8) -> Option<IrProtocols> {
+        if value & !IrProtocols::ALL.0 == 0 { Some(IrProtocols(value)) } else { None }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IrCode {
+    pub protocol: IrType,
+    pub addr: u8,
+    pub cmd: u8,
+    // Sent by the remote while the button is held down, after the first code
+    pub repeat: bool,
+}
+
+impl IrCode {
+    pub const fn new(protocol: IrType, addr: u8, cmd: u8) -> Self {
+        IrCode { protocol, addr, cmd, repeat: false }
+    }
+
+    // The code of the first press, which identifies the button whether or not it's being held
+    pub fn button(self) -> Self {
+        IrCode { repeat: false, ..self }
+    }
+}
+
+
+// The receiver that codes are read from, which keeps them until they're polled
+pub trait IrInterface {
+    fn poll(&mut self) -> Option<IrCode>;
+    // The number of codes that have been dropped because they weren't polled in time
+    fn overflows(&self) -> u32;
+}
+
+
+// A queue of codes from the interrupt handler that receives them to the main loop.  It doesn't need a lock because
+// the producer only moves the tail, and only writes to the slot at the tail while it's outside of the queue, and the
+// consumer only moves the head.  Pushing or popping from more than one place at the same time fails instead of
+// corrupting the queue.  When the queue is full, the newest code is dropped and counted
+pub struct IrQueue {
+    codes: UnsafeCell<[IrCode; IR_QUEUE_SIZE]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    pushing: AtomicBool,
+    popping: AtomicBool,
+    overflows: AtomicU32,
+}
+
+unsafe impl Sync for IrQueue { }
+
+impl Default for IrQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrQueue {
+    pub const fn new() -> Self {
+        IrQueue {
+            codes: UnsafeCell::new([IrCode::new(IrType::Nec, 0, 0); IR_QUEUE_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            pushing: AtomicBool::new(false),
+            popping: AtomicBool::new(false),
+            overflows: AtomicU32::new(0),
+        }
+    }
+
+    pub fn push(&self, code: IrCode) -> bool {
+        if self.pushing.swap(true, Ordering::Acquire) {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % IR_QUEUE_SIZE;
+        let pushed = next != self.head.load(Ordering::Acquire);
+        if pushed {
+            // Only the slot is accessed, because the consumer could be reading another one
+            unsafe { (self.codes.get() as *mut IrCode).add(tail).write(code) };
+            self.tail.store(next, Ordering::Release);
+        } else {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.pushing.store(false, Ordering::Release);
+        pushed
+    }
+
+    pub fn pop(&self) -> Option<IrCode> {
+        if self.popping.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        let code = if head != self.tail.load(Ordering::Acquire) {
+            let code = unsafe { (self.codes.get() as *const IrCode).add(head).read() };
+            self.head.store((head + 1) % IR_QUEUE_SIZE, Ordering::Release);
+            Some(code)
+        } else {
+            None
+        };
+
+        self.popping.store(false, Ordering::Release);
+        code
+    }
+
+    pub fn overflows(&self) -> u32 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+
+// The addresses of the remotes that are listened to, where an empty filter accepts every remote
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct IrFilter {
+    addrs: [u8; IR_FILTER_MAX],
+    len: u8,
+}
+
+impl IrFilter {
+    pub fn new(addrs: &[u8]) -> Result<IrFilter, IrError> {
+        if addrs.len() > IR_FILTER_MAX {
+            return Err(IrError);
+        }
+        let mut filter = IrFilter::default();
+        filter.addrs[..addrs.len()].copy_from_slice(addrs);
+        filter.len = addrs.len() as u8;
+        Ok(filter)
+    }
+
+    pub fn addresses(&self) -> &[u8] {
+        &self.addrs[..self.len as usize]
+    }
+
+    pub fn accepts(&self, code: IrCode) -> bool {
+        self.len == 0 || self.addresses().contains(&code.addr)
+    }
+}
+
+
+// What a button on the remote does when it's pressed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IrAction {
+    Power,
+    IntensityUp,
+    IntensityDown,
+    IndexUp,
+    IndexDown,
+    Sleep,
+    Sunrise,
+    Channel(u8),
+}
+
+const IR_ACTIONS: &[(&str, IrAction)] = &[
+    ("power", IrAction::Power),
+    ("intensityup", IrAction::IntensityUp),
+    ("intensitydown", IrAction::IntensityDown),
+    ("indexup", IrAction::IndexUp),
+    ("indexdown", IrAction::IndexDown),
+    ("sleep", IrAction::Sleep),
+    ("sunrise", IrAction::Sunrise),
+];
+
+impl IrAction {
+    // Parse an action given as its name, or as "channel <n>"
+    pub fn parse(args: &[&str]) -> Option<IrAction> {
+        match args {
+            ["channel", channel] => lexical_core::parse::<u8>(channel.as_bytes()).ok().map(IrAction::Channel),
+            [name] => IR_ACTIONS.iter().find(|(action_name, _)| action_name == name).map(|(_, action)| *action),
+            _ => None,
+        }
+    }
+
+    // The buttons of the original remote, which still work unless their codes are bound to something else
+    pub fn default_for(code: IrCode) -> Option<IrAction> {
+        match (code.protocol, code.cmd) {
+            (IrType::Nec, 0x12) => Some(IrAction::Power),
+            (IrType::Nec, 0x1a) => Some(IrAction::IntensityUp),
+            (IrType::Nec, 0x1e) => Some(IrAction::IntensityDown),
+            (IrType::Nec, 0x16) => Some(IrAction::Sleep),
+            (IrType::Nec, cmd @ 1..=9) => Some(IrAction::Channel(cmd)),
+            _ => None,
+        }
+    }
+
+    fn encode(&self) -> [u8; 2] {
+        match self {
+            IrAction::Channel(channel) => [0, *channel],
+            action => [IR_ACTIONS.iter().position(|(_, other)| other == action).unwrap_or(0) as u8 + 1, 0],
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<IrAction> {
+        match data[0] {
+            0 => Some(IrAction::Channel(data[1])),
+            kind => IR_ACTIONS.get(kind as usize - 1).map(|(_, action)| *action),
+        }
+    }
+}
+
+// Actions are printed in the same form that they're parsed from
+impl fmt::Display for IrAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrAction::Channel(channel) => write!(f, "channel {}", channel),
+            action => {
+                let name = IR_ACTIONS.iter().find(|(_, other)| other == action).map(|(name, _)| *name).unwrap_or("");
+                write!(f, "{}", name)
+            },
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IrBinding {
+    pub code: IrCode,
+    pub action: IrAction,
+}
+
+impl IrBinding {
+    // Parse a binding given as "<protocol> <addr> <cmd> <action>"
+    pub fn parse(args: &[&str]) -> Option<IrBinding> {
+        match args {
+            [protocol, addr, cmd, action @ ..] => Some(IrBinding {
+                code: IrCode::new(
+                    IrType::parse(protocol)?,
+                    lexical_core::parse::<u8>(addr.as_bytes()).ok()?,
+                    lexical_core::parse::<u8>(cmd.as_bytes()).ok()?,
+                ),
+                action: IrAction::parse(action)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IrBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.code.protocol.name(), self.code.addr, self.code.cmd, self.action)
+    }
+}
+
+
+// Codes that have been learned from a remote, which are checked before the buttons of the original remote
+pub struct IrBindings {
+    bindings: [IrBinding; IR_BINDINGS_MAX],
+    len: usize,
+    modified: bool,
+}
+
+impl Default for IrBindings {
+    fn default() -> Self {
+        let unused = IrBinding { code: IrCode::new(IrType::Nec, 0, 0), action: IrAction::Power };
+        IrBindings {
+            bindings: [unused; IR_BINDINGS_MAX],
+            len: 0,
+            modified: false,
+        }
+    }
+}
+
+impl IrBindings {
+    pub fn bindings(&self) -> &[IrBinding] {
+        &self.bindings[..self.len]
+    }
+
+    pub fn find(&self, code: IrCode) -> Option<IrAction> {
+        self.bindings().iter().find(|binding| binding.code == code.button()).map(|binding| binding.action)
+    }
+
+    // A code can only have one action, so binding it again replaces the old action
+    pub fn bind(&mut self, mut binding: IrBinding) -> Result<(), IrError> {
+        binding.code = binding.code.button();
+        let index = match self.bindings().iter().position(|other| other.code == binding.code) {
+            Some(index) => index,
+            None if self.len < IR_BINDINGS_MAX => {
+                self.len += 1;
+                self.len - 1
+            },
+            None => return Err(IrError),
+        };
+        self.bindings[index] = binding;
+        self.modified = true;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.modified = true;
+    }
+
+    // Whether the bindings have changed since they were last saved or loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    // Layout: version, number of bindings, and then each binding as protocol, address, command, and the action's
+    // kind and argument
+    pub fn encode(&self, data: &mut [u8; IR_BINDINGS_ENCODED_SIZE]) {
+        *data = [0; IR_BINDINGS_ENCODED_SIZE];
+        data[0] = IR_BINDINGS_VERSION;
+        data[1] = self.len as u8;
+        for (binding, chunk) in self.bindings().iter().zip(data[2..].chunks_mut(BINDING_ENCODED_SIZE)) {
+            chunk[0..3].copy_from_slice(&[binding.code.protocol.to_u8(), binding.code.addr, binding.code.cmd]);
+            chunk[3..5].copy_from_slice(&binding.action.encode());
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<IrBindings> {
+        if data.len() != IR_BINDINGS_ENCODED_SIZE || data[0] != IR_BINDINGS_VERSION || data[1] as usize > IR_BINDINGS_MAX {
+            return None;
+        }
+
+        let mut bindings = IrBindings::default();
+        for chunk in data[2..].chunks(BINDING_ENCODED_SIZE).take(data[1] as usize) {
+            let code = IrCode::new(IrType::from_u8(chunk[0])?, chunk[1], chunk[2]);
+            bindings.bind(IrBinding { code, action: IrAction::decode(&chunk[3..5])? }).ok()?;
+        }
+        bindings.modified = false;
+        Some(bindings)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nec(addr: u8, cmd: u8) -> IrCode {
+        IrCode::new(IrType::Nec, addr, cmd)
+    }
+
+    #[test]
+    fn test_queue() {
+        let queue = IrQueue::new();
+        assert_eq!(queue.pop(), None);
+
+        // Goes around the end of the buffer a few times
+        for cmd in 0..20 {
+            assert!(queue.push(nec(0, cmd)));
+            assert!(queue.push(IrCode { repeat: true, ..nec(0, cmd) }));
+            assert_eq!(queue.pop(), Some(nec(0, cmd)));
+            assert_eq!(queue.pop().map(|code| code.repeat), Some(true));
+        }
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.overflows(), 0);
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let queue = IrQueue::new();
+        for cmd in 0..IR_QUEUE_SIZE as u8 + 2 {
+            queue.push(nec(0, cmd));
+        }
+        assert_eq!(queue.overflows(), 3);
+
+        // The oldest codes are kept
+        for cmd in 0..IR_QUEUE_SIZE as u8 - 1 {
+            assert_eq!(queue.pop(), Some(nec(0, cmd)));
+        }
+        assert_eq!(queue.pop(), None);
+        assert!(queue.push(nec(0, 99)));
+        assert_eq!(queue.pop(), Some(nec(0, 99)));
+    }
+
+    #[test]
+    fn test_queue_between_threads() {
+        let queue = std::sync::Arc::new(IrQueue::new());
+        let producer = queue.clone();
+        let thread = std::thread::spawn(move || {
+            let mut cmd = 0;
+            while cmd < 200 {
+                if producer.push(nec(0, cmd)) {
+                    cmd += 1;
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < 200 {
+            if let Some(code) = queue.pop() {
+                assert_eq!(code, nec(0, expected));
+                expected += 1;
+            }
+        }
+        thread.join().unwrap();
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_action_parse() {
+        assert_eq!(IrAction::parse(&["power"]), Some(IrAction::Power));
+        assert_eq!(IrAction::parse(&["channel", "12"]), Some(IrAction::Channel(12)));
+        assert_eq!(IrAction::parse(&["channel"]), None);
+        assert_eq!(IrAction::parse(&["bogus"]), None);
+
+        for line in &["nec 0 18 power", "rc5 4 7 channel 10", "samsung 7 2 indexup", "rc6 255 1 sunrise"] {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(format!("{}", IrBinding::parse(&args).unwrap()), *line);
+        }
+        assert_eq!(IrBinding::parse(&["nec", "256", "1", "power"]), None);
+    }
+
+    #[test]
+    fn test_default_actions() {
+        assert_eq!(IrAction::default_for(nec(0, 0x12)), Some(IrAction::Power));
+        assert_eq!(IrAction::default_for(nec(7, 5)), Some(IrAction::Channel(5)));
+        assert_eq!(IrAction::default_for(nec(0, 0x40)), None);
+        assert_eq!(IrAction::default_for(IrCode::new(IrType::Rc5, 0, 0x12)), None);
+    }
+
+    #[test]
+    fn test_protocols() {
+        let protocols = IrProtocols::parse(&["rc5", "nec"]).unwrap();
+        assert!(protocols.contains(IrType::Nec) && protocols.contains(IrType::Rc5));
+        assert!(!protocols.contains(IrType::Rc6));
+        assert_eq!(protocols.iter().collect::<Vec<_>>(), vec![IrType::Nec, IrType::Rc5]);
+        assert_eq!(IrProtocols::parse(&["all"]), Some(IrProtocols::ALL));
+        assert_eq!(IrProtocols::parse(&["nec", "sirc"]), None);
+        assert_eq!(IrProtocols::from_u8(protocols.to_u8()), Some(protocols));
+        assert_eq!(IrProtocols::from_u8(0x80), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        assert!(IrFilter::default().accepts(nec(9, 0x12)));
+
+        let filter = IrFilter::new(&[0, 64]).unwrap();
+        assert_eq!(filter.addresses(), &[0, 64]);
+        assert!(filter.accepts(nec(64, 0x12)));
+        assert!(!filter.accepts(nec(9, 0x12)));
+        assert_eq!(IrFilter::new(&[1, 2, 3, 4, 5]), Err(IrError));
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut bindings = IrBindings::default();
+        bindings.bind(IrBinding { code: nec(4, 0x40), action: IrAction::Power }).unwrap();
+        bindings.bind(IrBinding { code: nec(4, 0x41), action: IrAction::Channel(3) }).unwrap();
+        bindings.bind(IrBinding { code: nec(4, 0x40), action: IrAction::Sleep }).unwrap();
+        assert_eq!(bindings.bindings().len(), 2);
+        assert_eq!(bindings.find(nec(4, 0x40)), Some(IrAction::Sleep));
+        assert_eq!(bindings.find(nec(5, 0x40)), None);
+        assert_eq!(bindings.find(IrCode { repeat: true, ..nec(4, 0x40) }), Some(IrAction::Sleep));
+        assert_eq!(bindings.find(IrCode::new(IrType::Rc5, 4, 0x40)), None);
+
+        for cmd in 0..IR_BINDINGS_MAX as u8 - 2 {
+            bindings.bind(IrBinding { code: nec(0, cmd), action: IrAction::IndexUp }).unwrap();
+        }
+        assert_eq!(bindings.bind(IrBinding { code: nec(1, 0), action: IrAction::IndexUp }), Err(IrError));
+        assert!(bindings.is_modified());
+    }
+
+    #[test]
+    fn test_bindings_encoding() {
+        let mut bindings = IrBindings::default();
+        bindings.bind(IrBinding { code: nec(4, 0x40), action: IrAction::Sunrise }).unwrap();
+        bindings.bind(IrBinding { code: nec(4, 0x41), action: IrAction::Channel(13) }).unwrap();
+        bindings.bind(IrBinding { code: IrCode::new(IrType::Rc6, 4, 0x41), action: IrAction::Power }).unwrap();
+
+        let mut data = [0; IR_BINDINGS_ENCODED_SIZE];
+        bindings.encode(&mut data);
+        let decoded = IrBindings::decode(&data).unwrap();
+        assert_eq!(decoded.bindings(), bindings.bindings());
+        assert!(!decoded.is_modified());
+
+        data[5] = 99;
+        assert!(IrBindings::decode(&data).is_none());
+    }
+}
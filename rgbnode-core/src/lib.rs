@@ -0,0 +1,12 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod ir;
+pub mod rgb;
+pub mod curve;
+pub mod easing;
+pub mod palette;
+pub mod sequence;
+pub mod node;
+pub mod time;
+pub mod serial;
+pub mod settings;
@@ -0,0 +1,1895 @@
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use lexical_core;
+
+use crate::ir::{ IrInterface, IrCode, IrAction, IrBinding, IrBindings, IrFilter, IrProtocols, IrError, IR_BINDINGS_ENCODED_SIZE, IR_FILTER_MAX };
+use crate::curve::{ OutputCurve };
+use crate::easing::{ Easing };
+use crate::rgb::{ RgbDevice, RgbEngine, RgbMode, Colour, ColourSpace, EASED_MODES, EASED_MODE_COUNT };
+use crate::serial::{ SerialInterface, InputLine };
+use crate::time::{ Clock, TimeOfDay, DAY_MILLIS, time_passed };
+use crate::settings::{ FlashDevice, FlashError, Settings, SettingsStore, BlobStore };
+use crate::sequence::{ Sequence, Step, SequenceError, SEQUENCE_ENCODED_SIZE };
+use crate::palette::{ Palettes, PaletteUse, PaletteError, PALETTE_COUNT, PALETTE_USES, PALETTES_ENCODED_SIZE };
+
+
+// Changed settings are saved once they have been left alone for this long
+const AUTOSAVE_DELAY: u32 = 10_000;
+
+const VERSION: &str = "0.1";
+
+// The fields reported by the status command, which can also be read individually with get
+const STATUS_FIELDS: &[&str] = &["power", "channel", "mode", "index", "intensity", "delay", "transition", "powerfade", "easing", "fadespace", "breathe", "flicker", "sleep", "color", "version"];
+
+// Each press of the sleep button on the remote adds this much to the sleep timer, up to the maximum
+const SLEEP_STEP_MINUTES: u32 = 15;
+const SLEEP_MAX_MINUTES: u32 = 60;
+
+// Repeat codes are sent about every 110ms while a button is held, so a longer gap means it was let go
+const IR_REPEAT_TIMEOUT: u32 = 250;
+
+// The channels that run the sequence and the sunrise
+const SEQUENCE_CHANNEL: u8 = 13;
+const SUNRISE_CHANNEL: u8 = 14;
+
+// The pages of the flash device used for each kind of stored data
+pub const SETTINGS_FIRST_PAGE: usize = 0;
+pub const SETTINGS_PAGES: usize = 2;
+pub const PALETTES_FIRST_PAGE: usize = 2;
+pub const PALETTES_PAGES: usize = 2;
+pub const SEQUENCE_FIRST_PAGE: usize = 4;
+pub const SEQUENCE_PAGES: usize = 2;
+pub const IR_BINDINGS_FIRST_PAGE: usize = 6;
+pub const IR_BINDINGS_PAGES: usize = 2;
+
+// Identifies the kind of data in each blob store
+const PALETTES_KIND: u8 = 1;
+const SEQUENCE_KIND: u8 = 2;
+const IR_BINDINGS_KIND: u8 = 3;
+
+
+// The devices that a node is built from, which are different for the firmware and the host
+pub trait Platform {
+    type Rgb: RgbDevice;
+    type Serial: SerialInterface;
+    type Clock: Clock;
+    type Flash: FlashDevice;
+    type Ir: IrInterface;
+}
+
+struct Command<P: Platform> {
+    pub name: &'static str,
+    pub min: u8,
+    pub func: fn(&mut RgbNode<P>, &[&str]) -> (),
+}
+
+struct Commands<'a, P>(PhantomData<&'a P>);
+
+impl<'a, P: Platform + 'a> Commands<'a, P> {
+    const COMMANDS: &'a [Command<P>] = &[
+        Command { name: "power", min: 0, func: command_power },
+        Command { name: "red", min: 1, func: command_red },
+        Command { name: "green", min: 1, func: command_green },
+        Command { name: "blue", min: 1, func: command_blue },
+        Command { name: "delay", min: 1, func: command_delay },
+        Command { name: "index", min: 1, func: command_index },
+        Command { name: "channel", min: 1, func: command_channel },
+        Command { name: "intensity", min: 1, func: command_intensity },
+        Command { name: "indexup", min: 0, func: command_indexup },
+        Command { name: "indexdown", min: 0, func: command_indexdown },
+        Command { name: "version", min: 0, func: command_version },
+        Command { name: "curve", min: 0, func: command_curve },
+        Command { name: "calibrate", min: 0, func: command_calibrate },
+        Command { name: "save", min: 0, func: command_save },
+        Command { name: "load", min: 0, func: command_load },
+        Command { name: "factory-reset", min: 0, func: command_factory_reset },
+        Command { name: "status", min: 0, func: command_status },
+        Command { name: "get", min: 1, func: command_get },
+        Command { name: "color", min: 1, func: command_color },
+        Command { name: "transition", min: 0, func: command_transition },
+        Command { name: "powerfade", min: 0, func: command_powerfade },
+        Command { name: "easing", min: 0, func: command_easing },
+        Command { name: "fadespace", min: 0, func: command_fadespace },
+        Command { name: "breathe", min: 0, func: command_breathe },
+        Command { name: "flicker", min: 0, func: command_flicker },
+        Command { name: "palette", min: 1, func: command_palette },
+        Command { name: "sequence", min: 1, func: command_sequence },
+        Command { name: "sleep", min: 0, func: command_sleep },
+        Command { name: "sunrise", min: 0, func: command_sunrise },
+        Command { name: "time", min: 0, func: command_time },
+        Command { name: "irlearn", min: 1, func: command_irlearn },
+        Command { name: "irmap", min: 1, func: command_irmap },
+        Command { name: "iraddr", min: 0, func: command_iraddr },
+        Command { name: "irproto", min: 0, func: command_irproto },
+        Command { name: "irrepeat", min: 0, func: command_irrepeat },
+        Command { name: "diagnostics", min: 0, func: command_diagnostics },
+        //{ "ir", 2, command_ir },
+        //{ "key", 1, command_key },
+        //{ "chanup", 0, command_chanup },
+        //{ "chandown", 0, command_chandown },
+    ];
+}
+
+fn command_power<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        if let Ok(i) = lexical_core::parse::<i32>(args[1].as_bytes()) {
+            let now = rgbnode.clock.millis();
+            rgbnode.engine.power(&mut rgbnode.rgb, i > 0, now);
+        }
+    } else {
+        let now = rgbnode.clock.millis();
+        rgbnode.engine.toggle(&mut rgbnode.rgb, now);
+    }
+}
+
+fn command_red<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let mut colour = rgbnode.engine.get_colour();
+    if let Ok(i) = lexical_core::parse::<u8>(args[1].as_bytes()) {
+        colour.r = i;
+        rgbnode.transition_colour(colour, None);
+    }
+}
+
+fn command_green<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let mut colour = rgbnode.engine.get_colour();
+    if let Ok(i) = lexical_core::parse::<u8>(args[1].as_bytes()) {
+        colour.g = i;
+        rgbnode.transition_colour(colour, None);
+    }
+}
+
+fn command_blue<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let mut colour = rgbnode.engine.get_colour();
+    if let Ok(i) = lexical_core::parse::<u8>(args[1].as_bytes()) {
+        colour.b = i;
+        rgbnode.transition_colour(colour, None);
+    }
+}
+
+fn command_color<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let (colour, count) = match Colour::parse(&args[1..]) {
+        Some(result) => result,
+        None => return rgbnode.return_error(),
+    };
+
+    // An optional fade time can follow the colour, which overrides the default transition time
+    match &args[count + 1..] {
+        [] => rgbnode.transition_colour(colour, None),
+        ["fade", time] => match lexical_core::parse::<u32>(time.as_bytes()) {
+            Ok(time) => rgbnode.transition_colour(colour, Some(time)),
+            Err(_) => rgbnode.return_error(),
+        },
+        _ => rgbnode.return_error(),
+    }
+}
+
+fn command_transition<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match lexical_core::parse::<u32>(args[1].as_bytes()) {
+            Ok(ms) => { rgbnode.engine.transition(Some(ms)); },
+            Err(_) => rgbnode.return_error(),
+        }
+    } else {
+        let transition = rgbnode.engine.transition(None);
+        rgbnode.send_response_fmt(format_args!("transition {}", transition));
+    }
+}
+
+fn command_delay<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if let Ok(ms) = lexical_core::parse::<u32>(args[1].as_bytes()) {
+        rgbnode.engine.delay(Some(ms));
+        let now = rgbnode.clock.millis();
+        rgbnode.engine.force_update(now);
+    }
+}
+
+fn command_index<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if let Ok(i) = lexical_core::parse::<usize>(args[1].as_bytes()) {
+        rgbnode.engine.index(Some(i));
+        rgbnode.transition_index();
+    }
+}
+
+fn command_channel<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if let Ok(ch) = lexical_core::parse::<u8>(args[1].as_bytes()) {
+        rgbnode.change_channel(ch);
+    }
+}
+
+fn command_intensity<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if let Ok(i) = lexical_core::parse::<u8>(args[1].as_bytes()) {
+        rgbnode.transition_intensity(i);
+    }
+}
+
+fn command_indexup<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    rgbnode.engine.index_up();
+    rgbnode.transition_index();
+}
+
+fn command_indexdown<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    rgbnode.engine.index_down();
+    rgbnode.transition_index();
+}
+
+fn command_version<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    rgbnode.send_response_fmt(format_args!("version {}", VERSION));
+}
+
+fn command_curve<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match OutputCurve::parse(&args[1..]) {
+            Some(curve) => rgbnode.rgb.set_curve(curve),
+            None => rgbnode.return_error(),
+        }
+    } else {
+        match rgbnode.rgb.curve() {
+            OutputCurve::Gamma(gamma) => rgbnode.send_response_fmt(format_args!("curve gamma {}.{:02}", gamma / 100, gamma % 100)),
+            curve => rgbnode.send_response_fmt(format_args!("curve {}", curve.name())),
+        }
+    }
+}
+
+fn command_calibrate<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 3 {
+        let r = lexical_core::parse::<u8>(args[1].as_bytes());
+        let g = lexical_core::parse::<u8>(args[2].as_bytes());
+        let b = lexical_core::parse::<u8>(args[3].as_bytes());
+        match (r, g, b) {
+            (Ok(r), Ok(g), Ok(b)) => rgbnode.rgb.set_calibration(Colour::new(r, g, b)),
+            _ => rgbnode.return_error(),
+        }
+    } else if args.len() == 1 {
+        let calibration = rgbnode.rgb.calibration();
+        rgbnode.send_response_fmt(format_args!("calibrate {} {} {}", calibration.r, calibration.g, calibration.b));
+    } else {
+        rgbnode.return_error();
+    }
+}
+
+fn command_save<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    if rgbnode.save_settings().is_err() {
+        rgbnode.return_error();
+    }
+}
+
+fn command_load<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    if !rgbnode.load_settings() {
+        rgbnode.return_error();
+    }
+}
+
+fn command_factory_reset<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    if rgbnode.factory_reset().is_err() {
+        rgbnode.return_error();
+    }
+}
+
+fn command_status<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    rgbnode.write_fmt(format_args!("status"));
+    for name in STATUS_FIELDS {
+        if let Some(value) = rgbnode.status_field(name) {
+            rgbnode.write_fmt(format_args!(" {}={}", name, value));
+        }
+    }
+    rgbnode.send_response("");
+}
+
+// Counters for problems that are otherwise silent, printed in the same form as the status
+fn command_diagnostics<P: Platform>(rgbnode: &mut RgbNode<P>, _args: &[&str]) {
+    let overflows = rgbnode.ir.overflows();
+    rgbnode.send_response_fmt(format_args!("diagnostics iroverflows={}", overflows));
+}
+
+fn command_get<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match rgbnode.status_field(args[1]) {
+        Some(value) => rgbnode.send_response_fmt(format_args!("{} {}", args[1], value)),
+        None => rgbnode.return_error(),
+    }
+}
+
+fn command_powerfade<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match lexical_core::parse::<u32>(args[1].as_bytes()) {
+            Ok(ms) => { rgbnode.engine.power_transition(Some(ms)); },
+            Err(_) => rgbnode.return_error(),
+        }
+    } else {
+        let power_transition = rgbnode.engine.power_transition(None);
+        rgbnode.send_response_fmt(format_args!("powerfade {}", power_transition));
+    }
+}
+
+// Each animated mode and manual transitions have their own easing, eg. "easing swirl sine" or "easing transition
+// ease-out", and "easing sine" changes all of the animated modes at once
+fn command_easing<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match &args[1..] {
+        [] => {
+            for target in EASED_MODES.iter().chain(&["transition"]) {
+                if let Some(easing) = named_easing(&mut rgbnode.engine, target, None) {
+                    rgbnode.send_response_fmt(format_args!("easing {} {}", target, easing.name()));
+                }
+            }
+        },
+        [target] => match named_easing(&mut rgbnode.engine, target, None) {
+            Some(easing) => rgbnode.send_response_fmt(format_args!("easing {} {}", target, easing.name())),
+            None => match Easing::parse(target) {
+                Some(easing) => { rgbnode.engine.easings(Some([easing; EASED_MODE_COUNT])); },
+                None => rgbnode.return_error(),
+            },
+        },
+        [target, name] => {
+            if Easing::parse(name).and_then(|easing| named_easing(&mut rgbnode.engine, target, Some(easing))).is_none() {
+                rgbnode.return_error();
+            }
+        },
+        _ => rgbnode.return_error(),
+    }
+}
+
+// The easing of one of the animated modes by name, or of manual transitions
+fn named_easing(engine: &mut RgbEngine, target: &str, update: Option<Easing>) -> Option<Easing> {
+    match target {
+        "transition" => Some(engine.transition_easing(update)),
+        _ => EASED_MODES.iter().position(|mode| *mode == target).map(|mode| engine.easing(mode, update)),
+    }
+}
+
+fn command_fadespace<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match ColourSpace::parse(args[1]) {
+            Some(space) => { rgbnode.engine.fade_space(Some(space)); },
+            None => rgbnode.return_error(),
+        }
+    } else {
+        let space = rgbnode.engine.fade_space(None);
+        rgbnode.send_response_fmt(format_args!("fadespace {}", space.name()));
+    }
+}
+
+fn command_breathe<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match lexical_core::parse::<u8>(args[1].as_bytes()) {
+            Ok(floor) => { rgbnode.engine.breathe_floor(Some(floor)); },
+            Err(_) => rgbnode.return_error(),
+        }
+    } else {
+        let floor = rgbnode.engine.breathe_floor(None);
+        rgbnode.send_response_fmt(format_args!("breathe {}", floor));
+    }
+}
+
+fn command_flicker<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match lexical_core::parse::<u8>(args[1].as_bytes()) {
+            Ok(amount) => { rgbnode.engine.flicker(Some(amount)); },
+            Err(_) => rgbnode.return_error(),
+        }
+    } else {
+        let amount = rgbnode.engine.flicker(None);
+        rgbnode.send_response_fmt(format_args!("flicker {}", amount));
+    }
+}
+
+// Palettes are edited one at a time, starting with the default palette, and each of the animated modes that
+// cycles through colours can be set to use any palette
+fn command_palette<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if edit_palette(rgbnode, &args[1..]).is_err() {
+        rgbnode.return_error();
+    }
+}
+
+fn edit_palette<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) -> Result<(), PaletteError> {
+    let editing = rgbnode.editing;
+
+    match args {
+        ["list"] => {
+            let palette = *rgbnode.engine.palettes().get(editing);
+            rgbnode.write_fmt(format_args!("palette {}", palette.name()));
+            for colour in palette.colours() {
+                rgbnode.write_fmt(format_args!(" {}", colour));
+            }
+            rgbnode.send_response("");
+        },
+        ["names"] => {
+            rgbnode.write_fmt(format_args!("palette names"));
+            for slot in 0..PALETTE_COUNT {
+                let palette = *rgbnode.engine.palettes().get(slot);
+                if palette.is_used() {
+                    rgbnode.write_fmt(format_args!(" {}", palette.name()));
+                }
+            }
+            rgbnode.send_response("");
+        },
+        ["new", name] => {
+            rgbnode.editing = rgbnode.engine.palettes_mut().create(name)?;
+        },
+        ["edit", name] => {
+            rgbnode.editing = rgbnode.engine.palettes().find(name).ok_or(PaletteError)?;
+        },
+        ["delete", name] => {
+            let slot = rgbnode.engine.palettes().find(name).ok_or(PaletteError)?;
+            rgbnode.engine.palettes_mut().delete(slot)?;
+            if slot == editing {
+                rgbnode.editing = 0;
+            }
+        },
+        ["set", index, colour @ ..] => {
+            let index = lexical_core::parse::<usize>(index.as_bytes()).map_err(|_| PaletteError)?;
+            match Colour::parse(colour) {
+                Some((colour, used)) if used == args.len() - 2 => rgbnode.engine.palettes_mut().set_colour(editing, index, colour)?,
+                _ => return Err(PaletteError),
+            }
+        },
+        ["clear"] => {
+            rgbnode.engine.palettes_mut().clear(editing);
+        },
+        ["use"] => {
+            rgbnode.write_fmt(format_args!("palette use"));
+            for (name, usage) in PALETTE_USES {
+                let palettes = rgbnode.engine.palettes();
+                let palette = *palettes.get(palettes.selected(*usage));
+                rgbnode.write_fmt(format_args!(" {} {}", name, palette.name()));
+            }
+            rgbnode.send_response("");
+        },
+        ["use", name] => {
+            let slot = rgbnode.engine.palettes().find(name).ok_or(PaletteError)?;
+            for (_, usage) in PALETTE_USES {
+                rgbnode.engine.palettes_mut().select(*usage, slot);
+            }
+        },
+        ["use", name, usage] => {
+            let slot = rgbnode.engine.palettes().find(name).ok_or(PaletteError)?;
+            let usage = PaletteUse::parse(usage).ok_or(PaletteError)?;
+            rgbnode.engine.palettes_mut().select(usage, slot);
+        },
+        _ => return Err(PaletteError),
+    }
+    Ok(())
+}
+
+// Sequences are built up one step at a time, and restart whenever they're changed while running
+fn command_sequence<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let result = match &args[1..] {
+        ["add", step @ ..] => Step::parse(step).ok_or(SequenceError).and_then(|step| rgbnode.engine.sequence_mut().push(step)),
+        ["clear"] => {
+            rgbnode.engine.sequence_mut().clear();
+            Ok(())
+        },
+        ["list"] => {
+            let count = rgbnode.engine.sequence().steps().len();
+            rgbnode.send_response_fmt(format_args!("sequence steps {}", count));
+            for i in 0..count {
+                let step = rgbnode.engine.sequence().steps()[i];
+                rgbnode.send_response_fmt(format_args!("sequence add {}", step));
+            }
+            return;
+        },
+        ["run"] => {
+            rgbnode.change_channel(SEQUENCE_CHANNEL);
+            return;
+        },
+        _ => Err(SequenceError),
+    };
+
+    match result {
+        Ok(()) if rgbnode.channel == SEQUENCE_CHANNEL => rgbnode.change_channel(SEQUENCE_CHANNEL),
+        Ok(()) => { },
+        Err(_) => rgbnode.return_error(),
+    }
+}
+
+fn command_sleep<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match lexical_core::parse::<u16>(args[1].as_bytes()) {
+            Ok(minutes) if rgbnode.engine.enabled() || minutes == 0 => rgbnode.start_sleep(minutes as u32),
+            _ => rgbnode.return_error(),
+        }
+    } else {
+        let minutes = rgbnode.sleep_minutes();
+        rgbnode.send_response_fmt(format_args!("sleep {}", minutes));
+    }
+}
+
+// The sunrise can be started now, or every day so that it finishes at the alarm time
+fn command_sunrise<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match &args[1..] {
+        [] => {
+            let minutes = rgbnode.engine.sunrise_time(None) / 60_000;
+            rgbnode.send_response_fmt(format_args!("sunrise minutes {}", minutes));
+            match rgbnode.sunrise_alarm {
+                Some(alarm) => rgbnode.send_response_fmt(format_args!("sunrise at {:02}:{:02}", alarm / 60, alarm % 60)),
+                None => rgbnode.send_response("sunrise at off"),
+            }
+        },
+        ["start"] => rgbnode.start_sunrise(),
+        ["minutes", minutes] => match lexical_core::parse::<u8>(minutes.as_bytes()) {
+            Ok(minutes) if minutes > 0 => { rgbnode.engine.sunrise_time(Some(minutes as u32 * 60_000)); },
+            _ => rgbnode.return_error(),
+        },
+        ["at", "off"] => rgbnode.sunrise_alarm = None,
+        ["at", time] => match TimeOfDay::parse_minutes(time) {
+            Some(minutes) => rgbnode.sunrise_alarm = Some(minutes),
+            None => rgbnode.return_error(),
+        },
+        _ => rgbnode.return_error(),
+    }
+}
+
+fn command_time<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match TimeOfDay::parse_minutes(args[1]) {
+            Some(minutes) => rgbnode.set_time(minutes),
+            None => rgbnode.return_error(),
+        }
+    } else {
+        let now = rgbnode.clock.millis();
+        match rgbnode.time_of_day.as_mut().map(|time| time.millis(now) / 60_000) {
+            Some(minutes) => rgbnode.send_response_fmt(format_args!("time {:02}:{:02}", minutes / 60, minutes % 60)),
+            None => rgbnode.return_error(),
+        }
+    }
+}
+
+// The next code received from a remote is bound to the action, instead of doing anything
+fn command_irlearn<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match &args[1..] {
+        ["cancel"] => rgbnode.learning = None,
+        action => match IrAction::parse(action) {
+            Some(action) => rgbnode.learning = Some(action),
+            None => rgbnode.return_error(),
+        },
+    }
+}
+
+fn command_irmap<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    let result = match &args[1..] {
+        ["add", binding @ ..] => IrBinding::parse(binding).ok_or(IrError).and_then(|binding| rgbnode.ir_bindings.bind(binding)),
+        ["clear"] => {
+            rgbnode.ir_bindings.clear();
+            Ok(())
+        },
+        ["list"] => {
+            let count = rgbnode.ir_bindings.bindings().len();
+            rgbnode.send_response_fmt(format_args!("irmap bindings {}", count));
+            for i in 0..count {
+                let binding = rgbnode.ir_bindings.bindings()[i];
+                rgbnode.send_response_fmt(format_args!("irmap add {}", binding));
+            }
+            return;
+        },
+        _ => Err(IrError),
+    };
+
+    if result.is_err() {
+        rgbnode.return_error();
+    }
+}
+
+// Only listen to remotes with the given addresses, or to any remote
+fn command_iraddr<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match &args[1..] {
+        [] => {
+            let filter = rgbnode.ir_filter;
+            if filter.addresses().is_empty() {
+                rgbnode.send_response("iraddr any");
+            } else {
+                rgbnode.write_fmt(format_args!("iraddr"));
+                for addr in filter.addresses() {
+                    rgbnode.write_fmt(format_args!(" {}", addr));
+                }
+                rgbnode.send_response("");
+            }
+        },
+        ["any"] => rgbnode.ir_filter = IrFilter::default(),
+        addrs if addrs.len() <= IR_FILTER_MAX => {
+            let mut parsed = [0; IR_FILTER_MAX];
+            for (addr, arg) in parsed.iter_mut().zip(addrs) {
+                match lexical_core::parse::<u8>(arg.as_bytes()) {
+                    Ok(value) => *addr = value,
+                    Err(_) => return rgbnode.return_error(),
+                }
+            }
+            rgbnode.ir_filter = IrFilter::new(&parsed[..addrs.len()]).unwrap_or_default();
+        },
+        _ => rgbnode.return_error(),
+    }
+}
+
+// All of the protocols are decoded at once, but the ones that aren't needed can be ignored
+fn command_irproto<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    if args.len() > 1 {
+        match IrProtocols::parse(&args[1..]) {
+            Some(protocols) => rgbnode.ir_protocols = protocols,
+            None => rgbnode.return_error(),
+        }
+    } else if rgbnode.ir_protocols == IrProtocols::ALL {
+        rgbnode.send_response("irproto all");
+    } else {
+        let protocols = rgbnode.ir_protocols;
+        rgbnode.write_fmt(format_args!("irproto"));
+        for protocol in protocols.iter() {
+            rgbnode.write_fmt(format_args!(" {}", protocol.name()));
+        }
+        rgbnode.send_response("");
+    }
+}
+
+fn command_irrepeat<P: Platform>(rgbnode: &mut RgbNode<P>, args: &[&str]) {
+    match &args[1..] {
+        [] => {
+            let (delay, acceleration) = (rgbnode.ir_repeat_delay, rgbnode.ir_acceleration);
+            rgbnode.send_response_fmt(format_args!("irrepeat {} {}", delay, acceleration));
+        },
+        [delay, acceleration] => {
+            let delay = lexical_core::parse::<u16>(delay.as_bytes());
+            let acceleration = lexical_core::parse::<u8>(acceleration.as_bytes());
+            match (delay, acceleration) {
+                (Ok(delay), Ok(acceleration)) => {
+                    rgbnode.ir_repeat_delay = delay;
+                    rgbnode.ir_acceleration = acceleration;
+                },
+                _ => rgbnode.return_error(),
+            }
+        },
+        _ => rgbnode.return_error(),
+    }
+}
+
+
+pub enum StatusValue {
+    Number(u32),
+    Name(&'static str),
+    Colour(Colour),
+}
+
+impl fmt::Display for StatusValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatusValue::Number(value) => write!(f, "{}", value),
+            StatusValue::Name(name) => write!(f, "{}", name),
+            StatusValue::Colour(colour) => write!(f, "{}", colour),
+        }
+    }
+}
+
+
+// The button being held down on the remote, and how many times it's been repeated since the repeat delay
+#[derive(Copy, Clone)]
+struct HeldButton {
+    action: IrAction,
+    pressed: u32,
+    last: u32,
+    repeats: u32,
+}
+
+
+pub struct RgbNode<P: Platform> {
+    pub rgb: P::Rgb,
+    pub engine: RgbEngine,
+    pub serial: P::Serial,
+    pub clock: P::Clock,
+    pub flash: P::Flash,
+    pub ir: P::Ir,
+    pub store: SettingsStore,
+    pub palette_store: BlobStore,
+    pub sequence_store: BlobStore,
+    pub ir_store: BlobStore,
+    ir_bindings: IrBindings,
+    learning: Option<IrAction>,
+    ir_filter: IrFilter,
+    ir_protocols: IrProtocols,
+    ir_repeat_delay: u16,
+    ir_acceleration: u8,
+    held: Option<HeldButton>,
+    channel: u8,
+    editing: usize,
+    time_of_day: Option<TimeOfDay>,
+    sunrise_alarm: Option<u16>,
+    checked: u32,
+    saved: Settings,
+    changed: Option<u32>,
+    sent: bool,
+}
+
+impl<P: Platform> RgbNode<P> {
+    pub fn new(rgb: P::Rgb, serial: P::Serial, clock: P::Clock, flash: P::Flash, ir: P::Ir) -> Self {
+        RgbNode {
+            rgb,
+            serial,
+            engine: RgbEngine::new(clock.millis() as u64),
+            clock,
+            flash,
+            ir,
+            store: SettingsStore::new(SETTINGS_FIRST_PAGE, SETTINGS_PAGES),
+            palette_store: BlobStore::new(PALETTES_FIRST_PAGE, PALETTES_PAGES, PALETTES_KIND),
+            sequence_store: BlobStore::new(SEQUENCE_FIRST_PAGE, SEQUENCE_PAGES, SEQUENCE_KIND),
+            ir_store: BlobStore::new(IR_BINDINGS_FIRST_PAGE, IR_BINDINGS_PAGES, IR_BINDINGS_KIND),
+            ir_bindings: IrBindings::default(),
+            learning: None,
+            ir_filter: IrFilter::default(),
+            ir_protocols: IrProtocols::ALL,
+            ir_repeat_delay: Settings::default().ir_repeat_delay,
+            ir_acceleration: Settings::default().ir_acceleration,
+            held: None,
+            channel: Settings::default().channel,
+            editing: 0,
+            time_of_day: None,
+            sunrise_alarm: None,
+            checked: 0,
+            saved: Settings::default(),
+            changed: None,
+            sent: false,
+        }
+    }
+
+    pub fn process_input(&mut self, input: &mut InputLine) {
+        if self.serial.poll_read(input) {
+            if let Ok(line) = input.to_str() {
+                self.process_command(line.trim_end());
+            } else {
+                self.return_error();
+            }
+            input.discard();
+        }
+    }
+
+    pub fn process_command(&mut self, line: &str) {
+        let mut i = 0;
+        let mut args: [&str; 10] = [""; 10];
+        for string in line.split_whitespace() {
+            // No command takes this many arguments, so the line can't be valid
+            if i == args.len() {
+                return self.return_error();
+            }
+            args[i] = string;
+            i += 1;
+        }
+        if args[0].is_empty() {
+            return;
+        }
+
+        self.sent = false;
+        for cmd in Commands::<P>::COMMANDS {
+            if cmd.name == args[0] {
+                if i > cmd.min as usize {
+                    (cmd.func)(self, &args[0..i]);
+
+                    if !self.sent {
+                        self.send_response(line);
+                    }
+                } else {
+                    self.return_error();
+                }
+                return;
+            }
+        }
+
+        // No Command Found
+        self.return_error();
+    }
+
+    pub fn handle_animation(&mut self) {
+        self.engine.handle_animation(&mut self.rgb, self.clock.millis());
+    }
+
+    pub fn handle_autosave(&mut self) {
+        let now = self.clock.millis();
+        let modified = self.engine.palettes().is_modified() || self.engine.sequence().is_modified() || self.ir_bindings.is_modified();
+        if self.settings() == self.saved && !modified {
+            self.changed = None;
+            return;
+        }
+
+        match self.changed {
+            None => self.changed = Some(now),
+            Some(changed) if now.wrapping_sub(changed) >= AUTOSAVE_DELAY => {
+                if self.save_settings().is_err() {
+                    // Try again later rather than on every loop
+                    self.changed = Some(now);
+                }
+            },
+            Some(_) => { },
+        }
+    }
+
+    // Start the sunrise when it's due, so that it finishes at the alarm time
+    pub fn handle_schedule(&mut self) {
+        let now = self.clock.millis();
+        let time = match self.time_of_day.as_mut() {
+            Some(time_of_day) => time_of_day.millis(now),
+            None => return,
+        };
+        let checked = core::mem::replace(&mut self.checked, time);
+
+        if let Some(alarm) = self.sunrise_alarm {
+            let length = self.engine.sunrise_time(None) % DAY_MILLIS;
+            let start = (alarm as u32 * 60_000 + DAY_MILLIS - length) % DAY_MILLIS;
+            if time_passed(checked, time, start) {
+                self.start_sunrise();
+            }
+        }
+    }
+
+    pub fn status_field(&mut self, name: &str) -> Option<StatusValue> {
+        match name {
+            "power" => Some(StatusValue::Number(self.engine.enabled() as u32)),
+            "channel" => Some(StatusValue::Number(self.channel as u32)),
+            "mode" => Some(StatusValue::Name(self.engine.mode().name())),
+            "index" => Some(StatusValue::Number(self.engine.index(None) as u32)),
+            "intensity" => Some(StatusValue::Number(self.engine.intensity(None) as u32)),
+            "delay" => Some(StatusValue::Number(self.engine.delay(None))),
+            "transition" => Some(StatusValue::Number(self.engine.transition(None))),
+            "powerfade" => Some(StatusValue::Number(self.engine.power_transition(None))),
+            "easing" => {
+                let mode = self.engine.mode().eased_mode()?;
+                Some(StatusValue::Name(self.engine.easing(mode, None).name()))
+            },
+            "fadespace" => Some(StatusValue::Name(self.engine.fade_space(None).name())),
+            "breathe" => Some(StatusValue::Number(self.engine.breathe_floor(None) as u32)),
+            "flicker" => Some(StatusValue::Number(self.engine.flicker(None) as u32)),
+            "sleep" => Some(StatusValue::Number(self.sleep_minutes())),
+            "color" => Some(StatusValue::Colour(self.engine.get_colour())),
+            "version" => Some(StatusValue::Name(VERSION)),
+            _ => None,
+        }
+    }
+
+    pub fn settings(&mut self) -> Settings {
+        Settings {
+            power: self.engine.enabled(),
+            channel: self.channel,
+            index: self.engine.index(None) as u8,
+            intensity: self.engine.intensity(None),
+            delay: self.engine.delay(None),
+            transition: self.engine.transition(None),
+            power_transition: self.engine.power_transition(None),
+            easings: self.engine.easings(None),
+            transition_easing: self.engine.transition_easing(None),
+            fade_space: self.engine.fade_space(None),
+            breathe_floor: self.engine.breathe_floor(None),
+            flicker: self.engine.flicker(None),
+            sunrise_minutes: (self.engine.sunrise_time(None) / 60_000) as u8,
+            sunrise_alarm: self.sunrise_alarm,
+            ir_filter: self.ir_filter,
+            ir_protocols: self.ir_protocols,
+            ir_repeat_delay: self.ir_repeat_delay,
+            ir_acceleration: self.ir_acceleration,
+            // The output colour is only meaningful when it's not being animated
+            colour: match self.engine.mode() {
+                RgbMode::Solid => self.engine.get_colour(),
+                _ => self.saved.colour,
+            },
+            calibration: self.rgb.calibration(),
+            curve: self.rgb.curve(),
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.rgb.set_curve(settings.curve);
+        self.rgb.set_calibration(settings.calibration);
+        self.engine.intensity(Some(settings.intensity));
+        self.engine.delay(Some(settings.delay));
+        self.engine.transition(Some(settings.transition));
+        self.engine.easings(Some(settings.easings));
+        self.engine.transition_easing(Some(settings.transition_easing));
+        self.engine.fade_space(Some(settings.fade_space));
+        self.engine.breathe_floor(Some(settings.breathe_floor));
+        self.engine.flicker(Some(settings.flicker));
+        self.engine.sunrise_time(Some(settings.sunrise_minutes as u32 * 60_000));
+        self.sunrise_alarm = settings.sunrise_alarm;
+        self.ir_filter = settings.ir_filter;
+        self.ir_protocols = settings.ir_protocols;
+        self.ir_repeat_delay = settings.ir_repeat_delay;
+        self.ir_acceleration = settings.ir_acceleration;
+        self.change_channel(settings.channel);
+        self.engine.index(Some(settings.index as usize));
+        self.engine.set_colour(settings.colour);
+        self.engine.force_update(self.clock.millis());
+        self.engine.power_transition(Some(settings.power_transition));
+        self.engine.power(&mut self.rgb, settings.power, self.clock.millis());
+    }
+
+    pub fn save_settings(&mut self) -> Result<(), FlashError> {
+        let settings = self.settings();
+        self.store.save(&mut self.flash, &settings)?;
+        self.saved = settings;
+        self.changed = None;
+
+        if self.engine.palettes().is_modified() {
+            let mut data = [0; PALETTES_ENCODED_SIZE];
+            self.engine.palettes().encode(&mut data);
+            self.palette_store.save(&mut self.flash, &data)?;
+            self.engine.palettes_mut().mark_saved();
+        }
+
+        if self.engine.sequence().is_modified() {
+            let mut data = [0; SEQUENCE_ENCODED_SIZE];
+            self.engine.sequence().encode(&mut data);
+            self.sequence_store.save(&mut self.flash, &data)?;
+            self.engine.sequence_mut().mark_saved();
+        }
+
+        if self.ir_bindings.is_modified() {
+            let mut data = [0; IR_BINDINGS_ENCODED_SIZE];
+            self.ir_bindings.encode(&mut data);
+            self.ir_store.save(&mut self.flash, &data)?;
+            self.ir_bindings.mark_saved();
+        }
+        Ok(())
+    }
+
+    pub fn load_settings(&mut self) -> bool {
+        // The palettes and sequence are loaded first, so they're ready when the mode is restored
+        let mut data = [0; PALETTES_ENCODED_SIZE];
+        if let Some(length) = self.palette_store.load(&mut self.flash, &mut data) {
+            if let Some(palettes) = Palettes::decode(&data[..length]) {
+                *self.engine.palettes_mut() = palettes;
+                self.editing = 0;
+            }
+        }
+
+        let mut data = [0; SEQUENCE_ENCODED_SIZE];
+        if let Some(length) = self.sequence_store.load(&mut self.flash, &mut data) {
+            if let Some(sequence) = Sequence::decode(&data[..length]) {
+                *self.engine.sequence_mut() = sequence;
+            }
+        }
+
+        let mut data = [0; IR_BINDINGS_ENCODED_SIZE];
+        if let Some(length) = self.ir_store.load(&mut self.flash, &mut data) {
+            if let Some(bindings) = IrBindings::decode(&data[..length]) {
+                self.ir_bindings = bindings;
+            }
+        }
+
+        match self.store.load(&mut self.flash) {
+            Some(settings) => {
+                self.apply_settings(&settings);
+                self.saved = settings;
+                self.changed = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Load the saved settings at startup, or use the defaults if there are none
+    pub fn restore_settings(&mut self) {
+        if !self.load_settings() {
+            self.apply_settings(&Settings::default());
+        }
+    }
+
+    pub fn factory_reset(&mut self) -> Result<(), FlashError> {
+        self.store.clear(&mut self.flash)?;
+        self.palette_store.clear(&mut self.flash)?;
+        self.sequence_store.clear(&mut self.flash)?;
+        self.ir_store.clear(&mut self.flash)?;
+        *self.engine.palettes_mut() = Palettes::default();
+        *self.engine.sequence_mut() = Sequence::default();
+        self.ir_bindings = IrBindings::default();
+        self.editing = 0;
+        self.saved = Settings::default();
+        self.changed = None;
+        self.apply_settings(&Settings::default());
+        Ok(())
+    }
+
+    fn send_response(&mut self, response: &str) {
+        self.sent = true;
+        self.serial.write(response.as_bytes());
+        self.serial.write("\n".as_bytes());
+    }
+
+    fn send_response_fmt(&mut self, args: fmt::Arguments) {
+        self.write_fmt(args);
+        self.serial.write("\n".as_bytes());
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) {
+        self.sent = true;
+        fmt::write(&mut SerialWriter(&mut self.serial), args).ok();
+    }
+
+    fn return_error(&mut self) {
+        self.sent = true;
+        self.serial.write("error\n".as_bytes());
+    }
+
+    pub fn change_channel(&mut self, ch: u8) {
+        match ch {
+            0 => self.engine.cycle_mode(),
+            1 => { self.engine.solid_mode(); self.engine.index(Some(26)); },
+            2 => { self.engine.solid_mode(); self.engine.index(Some(27)); },
+            3 => { self.engine.solid_mode(); self.engine.index(Some(28)); },
+            4 => self.engine.solid_mode(),
+            5 => self.engine.strobe_mode(false),
+            6 => self.engine.strobe_mode(true),
+            7 => self.engine.swirl_mode(false),
+            8 => self.engine.swirl_mode(true),
+            9 => self.engine.rainbow_mode(),
+            10 => self.engine.breathe_mode(),
+            11 => self.engine.flicker_mode(false),
+            12 => self.engine.flicker_mode(true),
+            SEQUENCE_CHANNEL => self.engine.sequence_mode(),
+            SUNRISE_CHANNEL => self.engine.sunrise_mode(),
+            _ => { return; },
+        }
+        self.channel = ch;
+        self.engine.force_update(self.clock.millis());
+        if let 1..=3 = ch {
+            self.transition_index();
+        }
+    }
+
+    // Fade out over the given number of minutes and then turn off, or cancel the sleep timer if it's 0
+    pub fn start_sleep(&mut self, minutes: u32) {
+        self.engine.sleep(minutes * 60_000, self.clock.millis());
+    }
+
+    // The minutes left on the sleep timer, rounded up, or 0 if it's not running
+    pub fn sleep_minutes(&self) -> u32 {
+        let remaining = self.engine.sleep_remaining(self.clock.millis()).unwrap_or(0);
+        remaining.div_ceil(60_000)
+    }
+
+    // Turn on and start the sunrise from off
+    pub fn start_sunrise(&mut self) {
+        self.change_channel(SUNRISE_CHANNEL);
+        self.engine.power(&mut self.rgb, true, self.clock.millis());
+    }
+
+    // Set the time of day, given in minutes since midnight
+    pub fn set_time(&mut self, minutes: u16) {
+        let now = self.clock.millis();
+        let mut time_of_day = TimeOfDay::new(minutes as u32 * 60_000, now);
+        self.checked = time_of_day.millis(now);
+        self.time_of_day = Some(time_of_day);
+    }
+
+    // Fade to the given colour, using the default transition time if no time is given
+    pub fn transition_colour(&mut self, colour: Colour, time: Option<u32>) {
+        let time = time.unwrap_or_else(|| self.engine.transition(None));
+        self.engine.fade_colour(colour, time, self.clock.millis());
+    }
+
+    pub fn transition_intensity(&mut self, intensity: u8) {
+        let time = self.engine.transition(None);
+        self.engine.fade_intensity(intensity, time, self.clock.millis());
+    }
+
+    pub fn transition_index(&mut self) {
+        let time = self.engine.transition(None);
+        self.engine.show_index(time, self.clock.millis());
+    }
+
+    pub fn process_ir_code(&mut self, code: IrCode) {
+        if !self.ir_protocols.contains(code.protocol) {
+            return;
+        }
+        if code.repeat {
+            return self.repeat_ir_code(code);
+        }
+
+        // A code that's being learned is only bound, and doesn't do anything until it's received again
+        if let Some(action) = self.learning.take() {
+            self.held = None;
+            let binding = IrBinding { code, action };
+            match self.ir_bindings.bind(binding) {
+                Ok(()) => self.send_response_fmt(format_args!("irmap add {}", binding)),
+                Err(_) => self.return_error(),
+            }
+            return;
+        }
+
+        let now = self.clock.millis();
+        let action = self.ir_action(code);
+        self.held = action.map(|action| HeldButton { action, pressed: now, last: now, repeats: 0 });
+        if let Some(action) = action {
+            self.run_ir_action(action);
+        }
+    }
+
+    // Learned codes already include the address of their remote, so only the buttons of the original remote are
+    // filtered
+    fn ir_action(&self, code: IrCode) -> Option<IrAction> {
+        match self.ir_bindings.find(code) {
+            Some(action) => Some(action),
+            None if self.ir_filter.accepts(code) => IrAction::default_for(code),
+            None => None,
+        }
+    }
+
+    // Holding the intensity buttons changes the intensity faster the longer they're held, and holding the index
+    // buttons steps through the colours.  The other buttons, like power, only act once for each press
+    fn repeat_ir_code(&mut self, code: IrCode) {
+        let now = self.clock.millis();
+        let action = self.ir_action(code);
+        let mut held = match self.held {
+            Some(held) if Some(held.action) == action && now.wrapping_sub(held.last) <= IR_REPEAT_TIMEOUT => held,
+            _ => {
+                self.held = None;
+                return;
+            },
+        };
+
+        let elapsed = now.wrapping_sub(held.last);
+        held.last = now;
+        if now.wrapping_sub(held.pressed) >= self.ir_repeat_delay as u32 {
+            held.repeats += 1;
+        }
+        self.held = Some(held);
+        if held.repeats == 0 {
+            return;
+        }
+
+        match held.action {
+            IrAction::IntensityUp | IrAction::IntensityDown => {
+                let speed = 1 + held.repeats * self.ir_acceleration as u32 / 16;
+                let intensity = self.engine.intensity(None);
+                let step = (((intensity >> 4) as u32 + 1) * speed).min(255) as u8;
+                let target = match held.action {
+                    IrAction::IntensityUp => intensity.saturating_add(step),
+                    _ => intensity.saturating_sub(step),
+                };
+                // Fading over the time since the last repeat keeps the change smooth
+                self.engine.fade_intensity(target, elapsed, now);
+            },
+            IrAction::IndexUp | IrAction::IndexDown => self.run_ir_action(held.action),
+            _ => { },
+        }
+    }
+
+    pub fn run_ir_action(&mut self, action: IrAction) {
+        match action {
+            IrAction::Power => {
+                self.engine.toggle(&mut self.rgb, self.clock.millis());
+            },
+            IrAction::IntensityUp => {
+                let intensity = self.engine.intensity(None);
+                self.transition_intensity(intensity.saturating_add((intensity >> 3) + 1));
+            },
+            IrAction::IntensityDown => {
+                let intensity = self.engine.intensity(None);
+                self.transition_intensity(intensity.saturating_sub((intensity >> 3) + 1));
+            },
+            IrAction::IndexUp => {
+                self.engine.index_up();
+                self.transition_index();
+            },
+            IrAction::IndexDown => {
+                self.engine.index_down();
+                self.transition_index();
+            },
+            IrAction::Sleep if self.engine.enabled() => {
+                let minutes = (self.sleep_minutes().div_ceil(SLEEP_STEP_MINUTES) + 1) * SLEEP_STEP_MINUTES;
+                self.start_sleep(if minutes > SLEEP_MAX_MINUTES { 0 } else { minutes });
+            },
+            IrAction::Sleep => { },
+            IrAction::Sunrise => {
+                self.start_sunrise();
+            },
+            IrAction::Channel(channel) => {
+                self.change_channel(channel);
+            },
+        }
+    }
+}
+
+
+struct SerialWriter<'a, S: SerialInterface>(&'a mut S);
+
+impl<'a, S: SerialInterface> fmt::Write for SerialWriter<'a, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::ir::IrType;
+    use crate::settings::tests::MockFlash;
+
+    struct MockRgb {
+        enabled: bool,
+        colour: Option<Colour>,
+        curve: OutputCurve,
+        calibration: Colour,
+    }
+
+    impl Default for MockRgb {
+        fn default() -> Self {
+            MockRgb { enabled: false, colour: None, curve: OutputCurve::Linear, calibration: Colour::new(255, 255, 255) }
+        }
+    }
+
+    impl RgbDevice for MockRgb {
+        fn enable(&mut self) { self.enabled = true; }
+        fn disable(&mut self) { self.enabled = false; }
+        fn set_colour(&mut self, val: Colour) { self.colour = Some(val); }
+        fn curve(&self) -> OutputCurve { self.curve }
+        fn set_curve(&mut self, curve: OutputCurve) { self.curve = curve; }
+        fn calibration(&self) -> Colour { self.calibration }
+        fn set_calibration(&mut self, calibration: Colour) { self.calibration = calibration; }
+    }
+
+    #[derive(Default)]
+    struct MockSerial {
+        output: String,
+    }
+
+    impl SerialInterface for MockSerial {
+        fn poll_read(&mut self, _input: &mut InputLine) -> bool { false }
+        fn write(&mut self, string: &[u8]) {
+            self.output.push_str(core::str::from_utf8(string).unwrap());
+        }
+    }
+
+    #[derive(Default)]
+    struct MockIr {
+        overflows: u32,
+    }
+
+    impl IrInterface for MockIr {
+        fn poll(&mut self) -> Option<IrCode> { None }
+        fn overflows(&self) -> u32 { self.overflows }
+    }
+
+    struct MockPlatform;
+
+    impl Platform for MockPlatform {
+        type Rgb = MockRgb;
+        type Serial = MockSerial;
+        type Clock = ManualClock;
+        type Flash = MockFlash;
+        type Ir = MockIr;
+    }
+
+    fn new_node() -> RgbNode<MockPlatform> {
+        RgbNode::new(MockRgb::default(), MockSerial::default(), ManualClock::new(0), MockFlash::with_page_size(8, 1024), MockIr::default())
+    }
+
+    #[test]
+    fn test_command_echoes_line() {
+        let mut node = new_node();
+        node.process_command("intensity 42");
+        assert_eq!(node.engine.intensity(None), 42);
+        assert_eq!(node.serial.output, "intensity 42\n");
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let mut node = new_node();
+        node.process_command("bogus");
+        node.process_command("red");
+        node.process_command("sequence add random 1 2 3 4 5 6 7 8");
+        assert_eq!(node.serial.output, "error\nerror\nerror\n");
+    }
+
+    #[test]
+    fn test_command_power() {
+        let mut node = new_node();
+        node.process_command("power 1");
+        assert!(node.rgb.enabled);
+        node.process_command("power");
+        assert!(!node.rgb.enabled);
+    }
+
+    #[test]
+    fn test_command_colour_channels() {
+        let mut node = new_node();
+        node.process_command("red 1");
+        node.process_command("green 2");
+        node.process_command("blue 3");
+        assert_eq!(node.engine.get_colour(), Colour::new(1, 2, 3));
+    }
+
+    #[test]
+    fn test_command_version() {
+        let mut node = new_node();
+        node.process_command("version");
+        assert_eq!(node.serial.output, "version 0.1\n");
+    }
+
+    #[test]
+    fn test_command_curve() {
+        let mut node = new_node();
+        node.process_command("curve");
+        node.process_command("curve gamma 2.5");
+        node.process_command("curve");
+        node.process_command("curve bogus");
+        assert_eq!(node.rgb.curve, OutputCurve::Gamma(250));
+        assert_eq!(node.serial.output, "curve linear\ncurve gamma 2.5\ncurve gamma 2.50\nerror\n");
+    }
+
+    #[test]
+    fn test_command_calibrate() {
+        let mut node = new_node();
+        node.process_command("calibrate 255 180 140");
+        node.process_command("calibrate");
+        node.process_command("calibrate 255 180");
+        node.process_command("calibrate 255 180 300");
+        assert_eq!(node.rgb.calibration, Colour::new(255, 180, 140));
+        assert_eq!(node.serial.output, "calibrate 255 180 140\ncalibrate 255 180 140\nerror\nerror\n");
+    }
+
+    #[test]
+    fn test_command_save_load() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("load");
+        node.process_command("channel 4");
+        node.process_command("red 10");
+        node.process_command("calibrate 255 200 100");
+        node.process_command("save");
+
+        node.process_command("channel 8");
+        node.process_command("intensity 10");
+        node.process_command("load");
+        assert_eq!(node.serial.output, "error\nchannel 4\nred 10\ncalibrate 255 200 100\nsave\nchannel 8\nintensity 10\nload\n");
+        assert_eq!(node.engine.intensity(None), 255);
+        assert_eq!(node.engine.get_colour(), Colour::new(10, 255, 255));
+        assert!(matches!(node.engine.mode(), RgbMode::Solid));
+
+        let mut restored = RgbNode::<MockPlatform>::new(MockRgb::default(), MockSerial::default(), ManualClock::new(0), node.flash, MockIr::default());
+        restored.restore_settings();
+        assert!(restored.rgb.enabled);
+        assert_eq!(restored.rgb.calibration, Colour::new(255, 200, 100));
+        assert_eq!(restored.engine.get_colour(), Colour::new(10, 255, 255));
+    }
+
+    #[test]
+    fn test_command_factory_reset() {
+        let mut node = new_node();
+        node.process_command("intensity 10");
+        node.process_command("save");
+        node.process_command("factory-reset");
+        assert_eq!(node.engine.intensity(None), 255);
+        assert_eq!(node.store.load(&mut node.flash), None);
+    }
+
+    #[test]
+    fn test_autosave() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("intensity 10");
+
+        node.handle_autosave();
+        node.clock.advance(AUTOSAVE_DELAY - 1);
+        node.handle_autosave();
+        assert_eq!(node.store.load(&mut node.flash), None);
+
+        node.clock.advance(1);
+        node.handle_autosave();
+        assert_eq!(node.store.load(&mut node.flash).map(|settings| settings.intensity), Some(10));
+    }
+
+    #[test]
+    fn test_command_status() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 4");
+        node.process_command("green 128");
+        node.serial.output.clear();
+
+        node.process_command("status");
+        node.process_command("get color");
+        node.process_command("get mode");
+        node.process_command("get bogus");
+        assert_eq!(node.serial.output, "status power=1 channel=4 mode=solid index=23 intensity=255 delay=5000 transition=0 powerfade=0 fadespace=rgb breathe=32 flicker=128 sleep=0 color=#ff80ff version=0.1\n\
+            color #ff80ff\nmode solid\nerror\n");
+    }
+
+    #[test]
+    fn test_command_color() {
+        let mut node = new_node();
+        node.process_command("color #102030");
+        assert_eq!(node.engine.get_colour(), Colour::new(0x10, 0x20, 0x30));
+        node.process_command("color 1 2 3");
+        assert_eq!(node.engine.get_colour(), Colour::new(1, 2, 3));
+        node.process_command("color hsv 120 255 255");
+        assert_eq!(node.engine.get_colour(), Colour::new(0, 255, 0));
+        node.process_command("color blue");
+        assert_eq!(node.engine.get_colour(), Colour::new(0, 0, 255));
+        node.process_command("color blue 1");
+        node.process_command("color #1020");
+        assert_eq!(node.engine.get_colour(), Colour::new(0, 0, 255));
+        assert!(node.serial.output.ends_with("color blue\nerror\nerror\n"));
+    }
+
+    #[test]
+    fn test_command_color_fade() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 4");
+        node.process_command("color black");
+        node.process_command("color #ff0000 fade 100");
+        node.process_command("color #ff0000 fade");
+        node.process_command("color #ff0000 bogus 100");
+
+        node.clock.advance(50);
+        node.handle_animation();
+        assert_ne!(node.engine.get_colour(), Colour::new(255, 0, 0));
+        node.clock.advance(50);
+        node.handle_animation();
+        assert_eq!(node.engine.get_colour(), Colour::new(255, 0, 0));
+        assert!(node.serial.output.ends_with("color #ff0000 fade 100\nerror\nerror\n"));
+    }
+
+    #[test]
+    fn test_command_transition() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 4");
+        node.process_command("transition 200");
+        node.process_command("transition");
+        node.process_command("intensity 55");
+        assert!(node.serial.output.ends_with("transition 200\ntransition 200\nintensity 55\n"));
+
+        node.clock.advance(100);
+        node.handle_animation();
+        assert_eq!(node.rgb.colour, Some(Colour::new(155, 155, 155)));
+        node.clock.advance(100);
+        node.handle_animation();
+        assert_eq!(node.rgb.colour, Some(Colour::new(55, 55, 55)));
+
+        node.process_command("index 8");
+        node.clock.advance(200);
+        node.handle_animation();
+        node.handle_animation();
+        assert_eq!(node.engine.get_colour(), Colour::new(0, 255, 0));
+    }
+
+    #[test]
+    fn test_command_easing() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("easing sine");
+        node.process_command("easing swirl ease-in-out");
+        node.process_command("easing transition ease-out");
+        node.serial.output.clear();
+        node.process_command("easing swirl");
+        node.process_command("easing transition");
+        node.process_command("easing bogus");
+        node.process_command("easing swirl bogus");
+        node.process_command("easing transition sine extra");
+        assert_eq!(node.serial.output, "easing swirl ease-in-out\neasing transition ease-out\nerror\nerror\nerror\n");
+
+        // Without a mode, all of the animated modes are changed
+        node.process_command("easing linear");
+        node.serial.output.clear();
+        node.process_command("easing");
+        let listing: String = EASED_MODES.iter().map(|mode| format!("easing {} linear\n", mode)).collect();
+        assert_eq!(node.serial.output, listing + "easing transition ease-out\n");
+
+        // The status shows the easing of the current mode
+        node.process_command("easing swirl sine");
+        node.process_command("channel 7");
+        node.serial.output.clear();
+        node.process_command("get easing");
+        assert_eq!(node.serial.output, "easing sine\n");
+
+        node.save_settings().unwrap();
+        node.process_command("easing linear");
+        assert!(node.load_settings());
+        assert_eq!(node.engine.easing(0, None), Easing::Sine);
+    }
+
+    #[test]
+    fn test_command_fadespace() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 4");
+        node.process_command("color red");
+        node.process_command("fadespace hsv");
+        node.process_command("fadespace");
+        node.process_command("fadespace cmyk");
+        assert!(node.serial.output.ends_with("fadespace hsv\nfadespace hsv\nerror\n"));
+
+        node.process_command("color blue fade 100");
+        node.clock.advance(50);
+        node.handle_animation();
+        assert_eq!(node.engine.get_colour(), Colour::new(255, 0, 255));
+    }
+
+    #[test]
+    fn test_command_breathe() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 10");
+        node.process_command("breathe 100");
+        node.process_command("breathe");
+        node.process_command("breathe 300");
+        node.process_command("get mode");
+        assert!(node.serial.output.ends_with("breathe 100\nbreathe 100\nerror\nmode breathe\n"));
+        assert_eq!(node.engine.breathe_floor(None), 100);
+    }
+
+    #[test]
+    fn test_command_flicker() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("channel 11");
+        node.process_command("get mode");
+        node.process_command("channel 12");
+        node.process_command("get mode");
+        node.process_command("flicker 40");
+        node.process_command("flicker");
+        node.process_command("flicker -1");
+        assert!(node.serial.output.ends_with("mode candle\nchannel 12\nmode flicker\nflicker 40\nflicker 40\nerror\n"));
+    }
+
+    #[test]
+    fn test_command_palette() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("palette new warm");
+        node.process_command("palette set 0 #ff2000");
+        node.process_command("palette set 1 255 128 0");
+        node.process_command("palette set 3 red");
+        node.process_command("palette set 1 #ff8000 extra");
+        node.process_command("palette list");
+        node.process_command("palette names");
+        node.process_command("palette use warm swirl");
+        node.process_command("palette use");
+        node.process_command("palette use cold");
+        assert!(node.serial.output.ends_with("palette set 1 255 128 0\nerror\nerror\n\
+            palette warm #ff2000 #ff8000\npalette names default warm\npalette use warm swirl\n\
+            palette use cycle default swirl warm strobe default\nerror\n"));
+
+        node.process_command("channel 7");
+        for _ in 0..4 {
+            node.clock.advance(10_000);
+            node.handle_animation();
+        }
+        assert!(node.engine.get_colour() == Colour::new(255, 32, 0) || node.engine.get_colour() == Colour::new(255, 128, 0));
+
+        // Palettes are saved along with the settings, and the default can be edited but not deleted
+        node.save_settings().unwrap();
+        node.process_command("palette delete warm");
+        node.process_command("palette delete default");
+        node.process_command("palette clear");
+        node.process_command("palette list");
+        assert!(node.serial.output.ends_with("palette delete warm\nerror\npalette clear\npalette default\n"));
+        assert!(node.load_settings());
+        node.process_command("palette edit warm");
+        node.process_command("palette list");
+        assert!(node.serial.output.ends_with("palette warm #ff2000 #ff8000\n"));
+    }
+
+    #[test]
+    fn test_palette_autosave() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.handle_autosave();
+        node.process_command("palette clear");
+        node.handle_autosave();
+        node.clock.advance(AUTOSAVE_DELAY);
+        node.handle_autosave();
+        assert!(!node.engine.palettes().is_modified());
+
+        let mut node = RgbNode::<MockPlatform>::new(MockRgb::default(), MockSerial::default(), ManualClock::new(0), node.flash, MockIr::default());
+        node.restore_settings();
+        assert_eq!(node.engine.palettes().get(0).colours().len(), 0);
+        node.process_command("factory-reset");
+        assert_eq!(node.engine.palettes().get(0).colours().len(), 24);
+    }
+
+    #[test]
+    fn test_command_sequence() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("sequence add set black");
+        node.process_command("sequence add fade 100 hsv 0 255 200");
+        node.process_command("sequence add hold 100");
+        node.process_command("sequence add loop 0");
+        node.process_command("sequence add hold");
+        node.process_command("sequence list");
+        assert!(node.serial.output.ends_with("sequence add loop 0\nerror\nsequence steps 4\n\
+            sequence add set #000000\nsequence add fade 100 #c80000\nsequence add hold 100\nsequence add loop 0\n"));
+
+        node.process_command("sequence run");
+        node.process_command("get mode");
+        assert!(node.serial.output.ends_with("mode sequence\n"));
+        node.clock.advance(50);
+        node.handle_animation();
+        assert_eq!(node.engine.get_colour(), Colour::new(100, 0, 0));
+
+        // The sequence is saved with the settings, and restored at startup
+        node.clock.advance(AUTOSAVE_DELAY);
+        node.handle_autosave();
+        node.clock.advance(AUTOSAVE_DELAY);
+        node.handle_autosave();
+        let mut node = RgbNode::<MockPlatform>::new(MockRgb::default(), MockSerial::default(), ManualClock::new(0), node.flash, MockIr::default());
+        node.restore_settings();
+        assert_eq!(node.engine.sequence().steps().len(), 4);
+        assert_eq!(node.engine.mode().name(), "sequence");
+    }
+
+    #[test]
+    fn test_command_sleep() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("sleep 30");
+        node.clock.advance(60_000);
+        node.process_command("sleep");
+        node.process_command("get sleep");
+        node.process_command("sleep x");
+        assert!(node.serial.output.ends_with("sleep 30\nsleep 29\nsleep 29\nerror\n"));
+
+        node.clock.advance(29 * 60_000);
+        node.handle_animation();
+        assert!(!node.engine.enabled());
+        node.process_command("sleep 10");
+        node.process_command("sleep");
+        assert!(node.serial.output.ends_with("error\nsleep 0\n"));
+    }
+
+    #[test]
+    fn test_ir_sleep_button() {
+        let mut node = new_node();
+        node.restore_settings();
+        let sleep = IrCode::new(IrType::Nec, 0, 0x16);
+        node.process_ir_code(sleep);
+        assert_eq!(node.sleep_minutes(), 15);
+        node.clock.advance(60_000);
+        node.process_ir_code(sleep);
+        assert_eq!(node.sleep_minutes(), 30);
+        node.process_ir_code(sleep);
+        node.process_ir_code(sleep);
+        assert_eq!(node.sleep_minutes(), 60);
+        node.process_ir_code(sleep);
+        assert_eq!(node.sleep_minutes(), 0);
+    }
+
+    #[test]
+    fn test_command_sunrise() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("intensity 40");
+        node.process_command("power 0");
+        node.process_command("sunrise minutes 20");
+        node.process_command("sunrise at 6:45");
+        node.process_command("sunrise minutes 0");
+        node.process_command("sunrise at 25:00");
+        node.serial.output.clear();
+
+        node.process_command("sunrise");
+        assert_eq!(node.serial.output, "sunrise minutes 20\nsunrise at 06:45\n");
+        assert_eq!(node.settings().sunrise_alarm, Some(405));
+
+        node.process_command("sunrise start");
+        assert!(node.engine.enabled());
+        assert_eq!(node.settings().intensity, 40);
+        assert_eq!(node.status_field("mode").unwrap().to_string(), "sunrise");
+        node.handle_animation();
+        assert_eq!(node.rgb.colour, Some(Colour::new(0, 0, 0)));
+
+        node.serial.output.clear();
+        node.process_command("sunrise at off");
+        node.process_command("sunrise");
+        assert_eq!(node.serial.output, "sunrise at off\nsunrise minutes 20\nsunrise at off\n");
+    }
+
+    #[test]
+    fn test_sunrise_schedule() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("power 0");
+        node.process_command("time");
+        node.process_command("time 6:00");
+        node.process_command("sunrise at 7:00");
+        node.serial.output.clear();
+
+        // The sunrise starts early enough to finish at the alarm time
+        node.clock.advance(29 * 60_000);
+        node.handle_schedule();
+        node.process_command("time");
+        assert_eq!(node.serial.output, "time 06:29\n");
+        assert!(!node.engine.enabled());
+
+        node.clock.advance(60_000);
+        node.handle_schedule();
+        assert!(node.engine.enabled());
+        assert_eq!(node.engine.mode().name(), "sunrise");
+
+        // And again the next day
+        node.process_command("power 0");
+        node.clock.advance(12 * 60 * 60_000);
+        node.handle_schedule();
+        assert!(!node.engine.enabled());
+        node.clock.advance(12 * 60 * 60_000);
+        node.handle_schedule();
+        assert!(node.engine.enabled());
+    }
+
+    #[test]
+    fn test_ir_learning() {
+        let mut node = new_node();
+        node.restore_settings();
+        let code = IrCode::new(IrType::Nec, 0x40, 0x12);
+        node.process_command("irlearn channel 10");
+        node.process_command("irlearn bogus");
+        node.serial.output.clear();
+
+        // The code is bound instead of turning the power off
+        node.process_ir_code(code);
+        assert!(node.engine.enabled());
+        assert_eq!(node.serial.output, "irmap add nec 64 18 channel 10\n");
+        node.process_ir_code(code);
+        assert_eq!(node.engine.mode().name(), "breathe");
+
+        // The original remote's buttons still work for codes that haven't been bound
+        node.process_ir_code(IrCode::new(IrType::Nec, 0, 0x12));
+        assert!(!node.engine.enabled());
+
+        node.process_command("irlearn power");
+        node.process_command("irlearn cancel");
+        node.process_ir_code(IrCode::new(IrType::Nec, 0x40, 0x13));
+        assert_eq!(node.ir_bindings.bindings().len(), 1);
+    }
+
+    #[test]
+    fn test_command_irmap() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("irmap add nec 64 1 indexup");
+        node.process_command("irmap add nec 64 2 sunrise");
+        node.process_command("irmap add nec 64 3 bogus");
+        node.process_command("irmap add sirc 64 3 power");
+        node.serial.output.clear();
+
+        node.process_command("irmap list");
+        assert_eq!(node.serial.output, "irmap bindings 2\nirmap add nec 64 1 indexup\nirmap add nec 64 2 sunrise\n");
+
+        // The bindings are saved along with the settings
+        node.process_command("save");
+        node.process_command("irmap clear");
+        assert!(node.ir_bindings.bindings().is_empty());
+        node.process_command("load");
+        assert_eq!(node.ir_bindings.bindings().len(), 2);
+
+        node.process_command("factory-reset");
+        assert!(node.ir_bindings.bindings().is_empty());
+        assert!(!node.load_settings());
+    }
+
+    #[test]
+    fn test_command_iraddr() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("iraddr 0 64");
+        node.process_command("iraddr 1 2 3 4 5");
+        node.process_command("iraddr 256");
+        node.serial.output.clear();
+        node.process_command("iraddr");
+        assert_eq!(node.serial.output, "iraddr 0 64\n");
+
+        // Codes from other remotes are ignored, unless they've been learned
+        node.process_ir_code(IrCode::new(IrType::Nec, 9, 0x12));
+        assert!(node.engine.enabled());
+        node.process_command("irmap add nec 9 18 power");
+        node.process_ir_code(IrCode::new(IrType::Nec, 9, 0x12));
+        assert!(!node.engine.enabled());
+        node.process_ir_code(IrCode::new(IrType::Nec, 64, 0x12));
+        assert!(node.engine.enabled());
+
+        node.process_command("save");
+        node.process_command("iraddr any");
+        assert_eq!(node.settings().ir_filter, IrFilter::default());
+        node.process_command("load");
+        assert_eq!(node.settings().ir_filter.addresses(), &[0, 64]);
+    }
+
+    #[test]
+    fn test_ir_protocols() {
+        let mut node = new_node();
+        node.restore_settings();
+        let rc5 = IrCode::new(IrType::Rc5, 0, 12);
+        node.process_command("irmap add rc5 0 12 power");
+        node.process_command("irproto nec sirc");
+        node.serial.output.clear();
+        node.process_command("irproto");
+        assert_eq!(node.serial.output, "irproto all\n");
+
+        // The same command on a different protocol isn't one of the original remote's buttons
+        node.process_ir_code(IrCode::new(IrType::Rc6, 0, 0x12));
+        assert!(node.engine.enabled());
+        node.process_ir_code(rc5);
+        assert!(!node.engine.enabled());
+
+        node.process_command("irproto nec samsung");
+        node.process_ir_code(rc5);
+        assert!(!node.engine.enabled());
+        node.serial.output.clear();
+        node.process_command("irproto");
+        assert_eq!(node.serial.output, "irproto nec samsung\n");
+        assert_eq!(node.settings().ir_protocols, IrProtocols::parse(&["samsung", "nec"]).unwrap());
+    }
+
+    #[test]
+    fn test_ir_repeat() {
+        let mut node = new_node();
+        node.restore_settings();
+        node.process_command("irrepeat 200 8");
+        node.process_command("irrepeat 200");
+        node.serial.output.clear();
+        node.process_command("irrepeat");
+        assert_eq!(node.serial.output, "irrepeat 200 8\n");
+
+        // Repeats are ignored until the repeat delay, and then the steps get bigger
+        node.process_command("intensity 64");
+        let down = IrCode::new(IrType::Nec, 0, 0x1e);
+        node.process_ir_code(down);
+        assert_eq!(node.engine.intensity(None), 55);
+        for expected in &[55, 51, 43, 37, 28, 22, 14, 10, 5] {
+            node.clock.advance(100);
+            node.process_ir_code(IrCode { repeat: true, ..down });
+            assert_eq!(node.engine.intensity(None), *expected);
+        }
+
+        // The intensity fades towards each step over the time between repeats
+        node.process_command("channel 4");
+        node.process_command("color white");
+        node.process_command("intensity 100");
+        let up = IrCode::new(IrType::Nec, 0, 0x1a);
+        node.process_ir_code(up);
+        assert_eq!(node.engine.intensity(None), 113);
+        node.clock.advance(200);
+        node.process_ir_code(IrCode { repeat: true, ..up });
+        assert_eq!(node.engine.intensity(None), 121);
+        node.clock.advance(50);
+        node.handle_animation();
+        assert_eq!(node.rgb.colour, Some(Colour::new(115, 115, 115)));
+
+        // A repeat after a gap, or of a different button, doesn't continue the hold
+        node.clock.advance(1000);
+        node.process_ir_code(IrCode { repeat: true, ..up });
+        node.clock.advance(100);
+        node.process_ir_code(IrCode { repeat: true, ..down });
+        assert_eq!(node.engine.intensity(None), 121);
+    }
+
+    #[test]
+    fn test_ir_repeat_edge_triggered() {
+        let mut node = new_node();
+        node.restore_settings();
+        let power = IrCode::new(IrType::Nec, 0, 0x12);
+        node.process_ir_code(power);
+        for _ in 0..10 {
+            node.clock.advance(100);
+            node.process_ir_code(IrCode { repeat: true, ..power });
+        }
+        assert!(!node.engine.enabled());
+
+        // Holding an index button keeps stepping through the colours
+        node.process_command("power 1");
+        node.process_command("channel 4");
+        node.process_command("index 0");
+        node.process_command("irmap add nec 0 64 indexup");
+        let up = IrCode::new(IrType::Nec, 0, 64);
+        node.process_ir_code(up);
+        for _ in 0..5 {
+            node.clock.advance(100);
+            node.process_ir_code(IrCode { repeat: true, ..up });
+        }
+        assert_eq!(node.engine.index(None), 4);
+    }
+
+    #[test]
+    fn test_command_diagnostics() {
+        let mut node = new_node();
+        node.process_command("diagnostics");
+        node.ir.overflows = 3;
+        node.process_command("diagnostics");
+        assert_eq!(node.serial.output, "diagnostics iroverflows=0\ndiagnostics iroverflows=3\n");
+    }
+}
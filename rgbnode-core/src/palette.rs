@@ -0,0 +1,275 @@
+
+use crate::rgb::{ Colour, COLOUR_INDEX };
+
+
+pub const PALETTE_COUNT: usize = 6;
+pub const PALETTE_MAX_COLOURS: usize = 24;
+pub const PALETTE_NAME_MAX: usize = 8;
+
+// The size of the palettes when encoded for saving to flash
+pub const PALETTES_ENCODED_SIZE: usize = 4 + PALETTE_COUNT * PALETTE_ENCODED_SIZE;
+const PALETTE_ENCODED_SIZE: usize = 2 + PALETTE_NAME_MAX + PALETTE_MAX_COLOURS * 3;
+const PALETTES_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PaletteError;
+
+// The animated modes that take their colours from a palette
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaletteUse {
+    Cycle,
+    Swirl,
+    Strobe,
+}
+
+pub const PALETTE_USES: &[(&str, PaletteUse)] = &[
+    ("cycle", PaletteUse::Cycle),
+    ("swirl", PaletteUse::Swirl),
+    ("strobe", PaletteUse::Strobe),
+];
+
+impl PaletteUse {
+    pub fn parse(name: &str) -> Option<PaletteUse> {
+        PALETTE_USES.iter().find(|(use_name, _)| *use_name == name).map(|(_, usage)| *usage)
+    }
+}
+
+
+// A palette with an empty name is an unused slot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Palette {
+    name: [u8; PALETTE_NAME_MAX],
+    name_len: u8,
+    colours: [Colour; PALETTE_MAX_COLOURS],
+    len: u8,
+}
+
+impl Palette {
+    const EMPTY: Palette = Palette {
+        name: [0; PALETTE_NAME_MAX],
+        name_len: 0,
+        colours: [Colour { r: 0, g: 0, b: 0 }; PALETTE_MAX_COLOURS],
+        len: 0,
+    };
+
+    fn new(name: &str) -> Self {
+        let mut palette = Palette::EMPTY;
+        palette.name[..name.len()].copy_from_slice(name.as_bytes());
+        palette.name_len = name.len() as u8;
+        palette
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("")
+    }
+
+    pub fn colours(&self) -> &[Colour] {
+        &self.colours[..self.len as usize]
+    }
+
+    pub fn is_used(&self) -> bool {
+        self.name_len > 0
+    }
+}
+
+
+// The first palette holds the built-in cycle colours to start with, and is used by all of the animated modes
+// until another palette is selected.  An empty palette also falls back to the built-in colours
+pub struct Palettes {
+    palettes: [Palette; PALETTE_COUNT],
+    selected: [u8; 3],
+    modified: bool,
+}
+
+impl Default for Palettes {
+    fn default() -> Self {
+        let mut default = Palette::new("default");
+        default.colours.copy_from_slice(&COLOUR_INDEX[..PALETTE_MAX_COLOURS]);
+        default.len = PALETTE_MAX_COLOURS as u8;
+
+        let mut palettes = [Palette::EMPTY; PALETTE_COUNT];
+        palettes[0] = default;
+        Palettes {
+            palettes,
+            selected: [0; 3],
+            modified: false,
+        }
+    }
+}
+
+impl Palettes {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.palettes.iter().position(|palette| palette.is_used() && palette.name() == name)
+    }
+
+    pub fn get(&self, slot: usize) -> &Palette {
+        &self.palettes[slot]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Palette> {
+        self.palettes.iter().filter(|palette| palette.is_used())
+    }
+
+    pub fn create(&mut self, name: &str) -> Result<usize, PaletteError> {
+        if name.is_empty() || name.len() > PALETTE_NAME_MAX || self.find(name).is_some() {
+            return Err(PaletteError);
+        }
+        let slot = self.palettes.iter().position(|palette| !palette.is_used()).ok_or(PaletteError)?;
+        self.palettes[slot] = Palette::new(name);
+        self.modified = true;
+        Ok(slot)
+    }
+
+    // The first palette can be cleared but not deleted, and any modes using a deleted palette go back to it
+    pub fn delete(&mut self, slot: usize) -> Result<(), PaletteError> {
+        if slot == 0 {
+            return Err(PaletteError);
+        }
+        self.palettes[slot] = Palette::EMPTY;
+        self.selected.iter_mut().filter(|selected| **selected as usize == slot).for_each(|selected| *selected = 0);
+        self.modified = true;
+        Ok(())
+    }
+
+    // Colours can be replaced, or added to the end of the palette
+    pub fn set_colour(&mut self, slot: usize, index: usize, colour: Colour) -> Result<(), PaletteError> {
+        let palette = &mut self.palettes[slot];
+        if index > palette.len as usize || index >= PALETTE_MAX_COLOURS {
+            return Err(PaletteError);
+        }
+        palette.colours[index] = colour;
+        palette.len = palette.len.max(index as u8 + 1);
+        self.modified = true;
+        Ok(())
+    }
+
+    pub fn clear(&mut self, slot: usize) {
+        self.palettes[slot].len = 0;
+        self.modified = true;
+    }
+
+    pub fn select(&mut self, usage: PaletteUse, slot: usize) {
+        self.selected[usage as usize] = slot as u8;
+        self.modified = true;
+    }
+
+    pub fn selected(&self, usage: PaletteUse) -> usize {
+        self.selected[usage as usize] as usize
+    }
+
+    pub fn colours_for(&self, usage: PaletteUse) -> &[Colour] {
+        match self.palettes[self.selected(usage)].colours() {
+            [] => &COLOUR_INDEX[..PALETTE_MAX_COLOURS],
+            colours => colours,
+        }
+    }
+
+    // Whether the palettes have changed since they were last saved or loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    // Layout: version, the selected palette for each use, and then each palette as its name length, name,
+    // number of colours, and the colours as r, g, b
+    pub fn encode(&self, data: &mut [u8; PALETTES_ENCODED_SIZE]) {
+        data[0] = PALETTES_VERSION;
+        data[1..4].copy_from_slice(&self.selected);
+        for (palette, chunk) in self.palettes.iter().zip(data[4..].chunks_mut(PALETTE_ENCODED_SIZE)) {
+            chunk[0] = palette.name_len;
+            chunk[1..1 + PALETTE_NAME_MAX].copy_from_slice(&palette.name);
+            chunk[1 + PALETTE_NAME_MAX] = palette.len;
+            for (colour, bytes) in palette.colours.iter().zip(chunk[2 + PALETTE_NAME_MAX..].chunks_mut(3)) {
+                bytes.copy_from_slice(&[colour.r, colour.g, colour.b]);
+            }
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Palettes> {
+        if data.len() != PALETTES_ENCODED_SIZE || data[0] != PALETTES_VERSION {
+            return None;
+        }
+
+        let mut palettes = Palettes {
+            palettes: [Palette::EMPTY; PALETTE_COUNT],
+            selected: [data[1], data[2], data[3]],
+            modified: false,
+        };
+        for (palette, chunk) in palettes.palettes.iter_mut().zip(data[4..].chunks(PALETTE_ENCODED_SIZE)) {
+            palette.name_len = chunk[0];
+            palette.name.copy_from_slice(&chunk[1..1 + PALETTE_NAME_MAX]);
+            palette.len = chunk[1 + PALETTE_NAME_MAX];
+            for (colour, bytes) in palette.colours.iter_mut().zip(chunk[2 + PALETTE_NAME_MAX..].chunks(3)) {
+                *colour = Colour::new(bytes[0], bytes[1], bytes[2]);
+            }
+            if palette.name_len as usize > PALETTE_NAME_MAX || palette.len as usize > PALETTE_MAX_COLOURS {
+                return None;
+            }
+        }
+
+        let valid = |slot: &u8| palettes.palettes.get(*slot as usize).map(|palette| palette.is_used()).unwrap_or(false);
+        if !palettes.palettes[0].is_used() || !palettes.selected.iter().all(valid) {
+            return None;
+        }
+        Some(palettes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_editing() {
+        let mut palettes = Palettes::default();
+        assert_eq!(palettes.colours_for(PaletteUse::Swirl).len(), PALETTE_MAX_COLOURS);
+
+        let warm = palettes.create("warm").unwrap();
+        assert_eq!(palettes.create("warm"), Err(PaletteError));
+        assert_eq!(palettes.create("toolongname"), Err(PaletteError));
+        assert_eq!(palettes.find("warm"), Some(warm));
+
+        palettes.set_colour(warm, 0, Colour::new(255, 0, 0)).unwrap();
+        palettes.set_colour(warm, 1, Colour::new(255, 128, 0)).unwrap();
+        palettes.set_colour(warm, 0, Colour::new(255, 32, 0)).unwrap();
+        assert_eq!(palettes.set_colour(warm, 3, Colour::new(0, 0, 0)), Err(PaletteError));
+        assert_eq!(palettes.get(warm).colours(), &[Colour::new(255, 32, 0), Colour::new(255, 128, 0)]);
+
+        palettes.select(PaletteUse::Swirl, warm);
+        assert_eq!(palettes.colours_for(PaletteUse::Swirl).len(), 2);
+        assert_eq!(palettes.colours_for(PaletteUse::Cycle).len(), PALETTE_MAX_COLOURS);
+
+        // Empty palettes use the built-in colours instead
+        palettes.clear(warm);
+        assert_eq!(palettes.colours_for(PaletteUse::Swirl).len(), PALETTE_MAX_COLOURS);
+
+        palettes.delete(warm).unwrap();
+        assert_eq!(palettes.selected(PaletteUse::Swirl), 0);
+        assert_eq!(palettes.find("warm"), None);
+        assert_eq!(palettes.delete(0), Err(PaletteError));
+        assert!(palettes.is_modified());
+    }
+
+    #[test]
+    fn test_palette_encoding() {
+        let mut palettes = Palettes::default();
+        let ocean = palettes.create("ocean").unwrap();
+        palettes.set_colour(ocean, 0, Colour::new(0, 64, 255)).unwrap();
+        palettes.select(PaletteUse::Strobe, ocean);
+
+        let mut data = [0; PALETTES_ENCODED_SIZE];
+        palettes.encode(&mut data);
+        let decoded = Palettes::decode(&data).unwrap();
+        assert_eq!(decoded.palettes, palettes.palettes);
+        assert_eq!(decoded.selected(PaletteUse::Strobe), ocean);
+        assert!(!decoded.is_modified());
+
+        data[1] = 5;
+        assert!(Palettes::decode(&data).is_none());
+        assert!(Palettes::decode(&data[1..]).is_none());
+    }
+}
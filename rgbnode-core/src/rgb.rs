@@ -0,0 +1,1481 @@
+
+use core::fmt;
+
+use oorandom::Rand32;
+
+use crate::curve::{ OutputCurve };
+use crate::easing::{ Easing };
+use crate::palette::{ Palettes, PaletteUse, PALETTE_MAX_COLOURS };
+use crate::sequence::{ Sequence, SequenceRunner, SequenceAction };
+
+
+pub trait RgbDevice {
+    fn enable(&mut self);
+    fn disable(&mut self);
+    fn set_colour(&mut self, val: Colour);
+    fn curve(&self) -> OutputCurve;
+    fn set_curve(&mut self, curve: OutputCurve);
+    fn calibration(&self) -> Colour;
+    fn set_calibration(&mut self, calibration: Colour);
+}
+
+
+// Fades are calculated from the fraction of their time that has elapsed, as a 16-bit fixed point number
+pub const PROGRESS_MAX: u32 = 1 << 16;
+
+pub fn progress(elapsed: u32, time: u32) -> u32 {
+    if elapsed >= time {
+        PROGRESS_MAX
+    } else {
+        ((elapsed as u64 * PROGRESS_MAX as u64) / time as u64) as u32
+    }
+}
+
+pub fn interpolate(start: u8, target: u8, progress: u32) -> u8 {
+    let diff = target as i32 - start as i32;
+    (start as i32 + ((diff * progress as i32 + (1 << 15)) >> 16)) as u8
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Colour {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// Hue is a fraction of a full turn around the colour wheel (0-65535), so it wraps around on its own
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hsv {
+    pub hue: u16,
+    pub sat: u8,
+    pub val: u8,
+}
+
+// The colour space that fades are interpolated in.  Fading around the hue keeps the saturation and brightness
+// constant, instead of passing through the dim or washed out colours in between
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColourSpace {
+    Rgb,
+    Hsv,
+}
+
+pub struct HoldFrame {
+    pub start: u32,
+    pub time: u32,
+}
+
+pub struct FadeFrame {
+    pub start: Colour,
+    pub target: Colour,
+    pub begin: u32,
+    pub time: u32,
+    pub easing: Easing,
+    pub space: ColourSpace,
+}
+
+// A linear change in a single value over time, used for adjustments that happen independently of the frames
+#[derive(Copy, Clone, Debug)]
+pub struct Ramp {
+    pub start: u8,
+    pub target: u8,
+    pub begin: u32,
+    pub time: u32,
+}
+
+pub enum Frame {
+    Stop,
+    Hold(HoldFrame),
+    Fade(FadeFrame),
+}
+
+impl FadeFrame {
+    pub fn colour_at(&self, now: u32) -> Colour {
+        // The millisecond counter will wrap around after ~49.7 days
+        let progress = self.easing.apply(progress(now.wrapping_sub(self.begin), self.time));
+        match self.space {
+            ColourSpace::Rgb => self.start.interpolate(self.target, progress),
+            ColourSpace::Hsv => self.start.to_hsv().interpolate(self.target.to_hsv(), progress).to_colour(),
+        }
+    }
+
+    pub fn is_done(&self, now: u32) -> bool {
+        now.wrapping_sub(self.begin) >= self.time
+    }
+}
+
+impl Frame {
+    pub fn new_fade(current: Colour, target: Colour, delay: u32, easing: Easing, space: ColourSpace, now: u32) -> Frame {
+        Frame::Fade(FadeFrame {
+            start: current,
+            target,
+            begin: now,
+            time: delay,
+            easing,
+            space,
+        })
+    }
+}
+
+impl Ramp {
+    pub fn new(start: u8, target: u8, time: u32, now: u32) -> Self {
+        Ramp { start, target, begin: now, time }
+    }
+
+    pub fn is_done(&self, now: u32) -> bool {
+        now.wrapping_sub(self.begin) >= self.time
+    }
+
+    pub fn value(&self, now: u32) -> u8 {
+        interpolate(self.start, self.target, progress(now.wrapping_sub(self.begin), self.time))
+    }
+}
+
+
+// The modes that fade between colours, which each have their own easing.  The random swirl uses the same one as
+// the swirl, and the candle uses the same one as the flicker
+pub const EASED_MODE_COUNT: usize = 6;
+pub const EASED_MODES: [&str; EASED_MODE_COUNT] = ["swirl", "rainbow", "breathe", "flicker", "sequence", "sunrise"];
+pub const DEFAULT_EASINGS: [Easing; EASED_MODE_COUNT] =
+    [Easing::Linear, Easing::Linear, Easing::Sine, Easing::Linear, Easing::Linear, Easing::Linear];
+
+pub enum RgbMode {
+    Solid,
+    Cycle(usize),
+    Strobe(bool, bool),
+    Swirl(bool, usize, bool),
+    Rainbow(usize),
+    Breathe(bool),
+    Flicker(bool, i32, i32),
+    Sequence(SequenceRunner),
+    Sunrise(usize),
+}
+
+impl RgbMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RgbMode::Solid => "solid",
+            RgbMode::Cycle(_) => "cycle",
+            RgbMode::Strobe(false, _) => "strobe",
+            RgbMode::Strobe(true, _) => "random-strobe",
+            RgbMode::Swirl(false, _, _) => "swirl",
+            RgbMode::Swirl(true, _, _) => "random-swirl",
+            RgbMode::Rainbow(_) => "rainbow",
+            RgbMode::Breathe(_) => "breathe",
+            RgbMode::Flicker(false, _, _) => "candle",
+            RgbMode::Flicker(true, _, _) => "flicker",
+            RgbMode::Sequence(_) => "sequence",
+            RgbMode::Sunrise(_) => "sunrise",
+        }
+    }
+
+    // The position of the mode in EASED_MODES, if it fades between colours
+    pub fn eased_mode(&self) -> Option<usize> {
+        match self {
+            RgbMode::Swirl(_, _, _) => Some(0),
+            RgbMode::Rainbow(_) => Some(1),
+            RgbMode::Breathe(_) => Some(2),
+            RgbMode::Flicker(_, _, _) => Some(3),
+            RgbMode::Sequence(_) => Some(4),
+            RgbMode::Sunrise(_) => Some(5),
+            RgbMode::Solid | RgbMode::Cycle(_) | RgbMode::Strobe(_, _) => None,
+        }
+    }
+}
+
+
+pub struct RgbEngine {
+    enabled: bool,
+    intensity: u8,
+    intensity_ramp: Option<Ramp>,
+    delay: u32,
+    transition: u32,
+    easings: [Easing; EASED_MODE_COUNT],
+    transition_easing: Easing,
+    fade_space: ColourSpace,
+    breathe_floor: u8,
+    flicker: u8,
+    sunrise_time: u32,
+    power_ramp: Option<Ramp>,
+    power_transition: u32,
+    sleep_ramp: Option<Ramp>,
+    index: usize,
+    output: Colour,
+    mode: RgbMode,
+    frame: Frame,
+    rand: Rand32,
+    palettes: Palettes,
+    sequence: Sequence,
+}
+
+impl RgbEngine {
+    pub fn new(seed: u64) -> Self {
+        RgbEngine {
+            enabled: false,
+            intensity: 255,
+            intensity_ramp: None,
+            delay: 5000,
+            transition: 0,
+            easings: DEFAULT_EASINGS,
+            transition_easing: Easing::Linear,
+            fade_space: ColourSpace::Rgb,
+            breathe_floor: 32,
+            flicker: 128,
+            sunrise_time: 30 * 60_000,
+            power_ramp: None,
+            power_transition: 0,
+            sleep_ramp: None,
+            index: PALETTE_MAX_COLOURS - 1,
+            output: Colour::new(0xff, 0xff, 0xff),
+            mode: RgbMode::Swirl(false, 0, false),
+            frame: Frame::Stop,
+            rand: Rand32::new(seed),
+            palettes: Palettes::default(),
+            sequence: Sequence::default(),
+        }
+    }
+
+    // Device Control Functions
+
+    pub fn power<D: RgbDevice>(&mut self, dev: &mut D, on: bool, now: u32) {
+        let level = match self.power_ramp {
+            Some(ramp) => ramp.value(now),
+            None if self.enabled => 255,
+            None => 0,
+        };
+        let target = if on { 255 } else { 0 };
+
+        // Any change to the power cancels the sleep timer
+        self.sleep_ramp = None;
+        self.enabled = on;
+        if self.power_transition == 0 || level == target {
+            self.power_ramp = None;
+            match self.enabled {
+                true => dev.enable(),
+                false => dev.disable(),
+            }
+            return;
+        }
+
+        // The device is disabled by handle_animation once it has faded out
+        if on && self.power_ramp.is_none() {
+            dev.set_colour(Colour::new(0, 0, 0));
+            dev.enable();
+        }
+        self.power_ramp = Some(Ramp::new(level, target, self.power_transition, now));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn toggle<D: RgbDevice>(&mut self, dev: &mut D, now: u32) {
+        self.power(dev, !self.enabled, now);
+    }
+
+    pub fn handle_animation<D: RgbDevice>(&mut self, dev: &mut D, now: u32) {
+        if !self.enabled && self.power_ramp.is_none() {
+            return;
+        }
+
+        // The output has already faded out, so it's turned off directly instead of fading out again with power()
+        if self.sleep_ramp.map(|ramp| ramp.is_done(now)).unwrap_or(false) {
+            self.sleep_ramp = None;
+            self.power_ramp = None;
+            self.enabled = false;
+            dev.disable();
+            return;
+        }
+
+        self.update_frame(now);
+        // The sunrise always ends at full brightness, but the intensity setting is kept for the other modes
+        let intensity = match self.mode {
+            RgbMode::Sunrise(_) => 255,
+            _ => self.current_intensity(now),
+        };
+        let level = self.power_ramp.map(|ramp| ramp.value(now)).unwrap_or(255);
+        let sleep_level = self.sleep_ramp.map(|ramp| ramp.value(now)).unwrap_or(255);
+        dev.set_colour(self.output.scale(intensity).scale(level).scale(sleep_level));
+
+        if self.power_ramp.map(|ramp| ramp.is_done(now)).unwrap_or(false) {
+            self.power_ramp = None;
+            if !self.enabled {
+                dev.disable();
+            }
+        }
+    }
+
+    // Fade out over the given time and then turn off, or cancel the sleep timer if the time is 0.  The intensity
+    // setting is left alone, so the output is at its usual brightness when it's turned on again
+    pub fn sleep(&mut self, time: u32, now: u32) {
+        let level = self.sleep_ramp.map(|ramp| ramp.value(now)).unwrap_or(255);
+        self.sleep_ramp = if time > 0 && self.enabled { Some(Ramp::new(level, 0, time, now)) } else { None };
+    }
+
+    // The time left before the sleep timer turns off the output, if it's running
+    pub fn sleep_remaining(&self, now: u32) -> Option<u32> {
+        self.sleep_ramp.map(|ramp| ramp.time.saturating_sub(now.wrapping_sub(ramp.begin)))
+    }
+
+    // Public Adjustment Functions
+
+    pub fn intensity(&mut self, update: Option<u8>) -> u8 {
+        if let Some(update) = update {
+            self.intensity = update;
+            self.intensity_ramp = None;
+        }
+        self.intensity
+    }
+
+    pub fn fade_intensity(&mut self, intensity: u8, time: u32, now: u32) {
+        let start = self.current_intensity(now);
+        self.intensity = intensity;
+        self.intensity_ramp = if time > 0 { Some(Ramp::new(start, intensity, time, now)) } else { None };
+    }
+
+    // The time used to fade in and out when the power is turned on or off
+    pub fn power_transition(&mut self, update: Option<u32>) -> u32 {
+        if let Some(update) = update {
+            self.power_transition = update;
+        }
+        self.power_transition
+    }
+
+    // The default time used to fade between manual colour and intensity changes
+    pub fn transition(&mut self, update: Option<u32>) -> u32 {
+        if let Some(update) = update {
+            self.transition = update;
+        }
+        self.transition
+    }
+
+    // The easing used by the fades of one of the animated modes, given by its position in EASED_MODES
+    pub fn easing(&mut self, mode: usize, update: Option<Easing>) -> Easing {
+        if let Some(update) = update {
+            self.easings[mode] = update;
+        }
+        self.easings[mode]
+    }
+
+    // The easings of all of the animated modes, in the same order as EASED_MODES
+    pub fn easings(&mut self, update: Option<[Easing; EASED_MODE_COUNT]>) -> [Easing; EASED_MODE_COUNT] {
+        if let Some(update) = update {
+            self.easings = update;
+        }
+        self.easings
+    }
+
+    // The easing used when fading between manual colour changes
+    pub fn transition_easing(&mut self, update: Option<Easing>) -> Easing {
+        if let Some(update) = update {
+            self.transition_easing = update;
+        }
+        self.transition_easing
+    }
+
+    // The colour space used by all fades between colours
+    pub fn fade_space(&mut self, update: Option<ColourSpace>) -> ColourSpace {
+        if let Some(update) = update {
+            self.fade_space = update;
+        }
+        self.fade_space
+    }
+
+    // The lowest brightness reached when breathing, as a fraction of the intensity (0-255)
+    pub fn breathe_floor(&mut self, update: Option<u8>) -> u8 {
+        if let Some(update) = update {
+            self.breathe_floor = update;
+        }
+        self.breathe_floor
+    }
+
+    // How strongly the flicker modes vary the brightness and hue (0-255)
+    pub fn flicker(&mut self, update: Option<u8>) -> u8 {
+        if let Some(update) = update {
+            self.flicker = update;
+        }
+        self.flicker
+    }
+
+    // The time taken for the sunrise to go from off to full brightness
+    pub fn sunrise_time(&mut self, update: Option<u32>) -> u32 {
+        if let Some(update) = update {
+            self.sunrise_time = update;
+        }
+        self.sunrise_time
+    }
+
+    pub fn delay(&mut self, update: Option<u32>) -> u32 {
+        if let Some(update) = update {
+            self.delay = update;
+        }
+        self.delay
+    }
+
+    pub fn index(&mut self, update: Option<usize>) -> usize {
+        if let Some(update) = update {
+            self.index = update % COLOUR_INDEX.len();
+        }
+        self.index
+    }
+
+    // Change the output to the indexed colour, if it's not being controlled by an animation
+    pub fn show_index(&mut self, time: u32, now: u32) {
+        if let RgbMode::Solid = self.mode {
+            self.fade_colour(COLOUR_INDEX[self.index], time, now);
+        }
+    }
+
+    pub fn index_up(&mut self) {
+        self.index = (self.index + 1) % COLOUR_INDEX.len();
+    }
+
+    pub fn index_down(&mut self) {
+        if self.index == 0 {
+            self.index = COLOUR_INDEX.len() - 1;
+        } else {
+            self.index -= 1;
+        }
+    }
+
+    pub fn get_colour(&self) -> Colour {
+        self.output
+    }
+
+    pub fn set_colour(&mut self, colour: Colour) {
+        self.output = colour;
+        // A fade that's still running would otherwise overwrite the new colour with its own
+        if let Frame::Fade(_) = self.frame {
+            self.frame = Frame::Stop;
+        }
+    }
+
+    pub fn fade_colour(&mut self, colour: Colour, time: u32, now: u32) {
+        if time > 0 {
+            self.frame = Frame::new_fade(self.output, colour, time, self.transition_easing, self.fade_space, now);
+        } else {
+            self.set_colour(colour);
+        }
+    }
+
+    pub fn palettes(&self) -> &Palettes {
+        &self.palettes
+    }
+
+    pub fn palettes_mut(&mut self) -> &mut Palettes {
+        &mut self.palettes
+    }
+
+    pub fn sequence(&self) -> &Sequence {
+        &self.sequence
+    }
+
+    pub fn sequence_mut(&mut self) -> &mut Sequence {
+        &mut self.sequence
+    }
+
+    pub fn mode(&self) -> &RgbMode {
+        &self.mode
+    }
+
+    pub fn solid_mode(&mut self) {
+        self.mode = RgbMode::Solid;
+    }
+
+    pub fn cycle_mode(&mut self) {
+        self.mode = RgbMode::Cycle(0);
+    }
+
+    pub fn swirl_mode(&mut self, random: bool) {
+        self.mode = RgbMode::Swirl(random, 0, false);
+    }
+
+    pub fn rainbow_mode(&mut self) {
+        self.mode = RgbMode::Rainbow(0);
+    }
+
+    pub fn breathe_mode(&mut self) {
+        self.mode = RgbMode::Breathe(false);
+    }
+
+    // Flicker around a warm candle colour, or around the index colour if use_index is true
+    pub fn flicker_mode(&mut self, use_index: bool) {
+        self.mode = RgbMode::Flicker(use_index, 0, 0);
+    }
+
+    // Run the sequence from the start
+    pub fn sequence_mode(&mut self) {
+        self.mode = RgbMode::Sequence(SequenceRunner::default());
+    }
+
+    // Start the sunrise from off
+    pub fn sunrise_mode(&mut self) {
+        self.mode = RgbMode::Sunrise(0);
+    }
+
+    pub fn strobe_mode(&mut self, random: bool) {
+        self.mode = RgbMode::Strobe(random, false);
+    }
+
+    pub fn force_update(&mut self, now: u32) {
+        self.frame = self.get_next_frame(now);
+    }
+
+
+    // Private State Control Functions
+
+    fn current_intensity(&mut self, now: u32) -> u8 {
+        match self.intensity_ramp {
+            Some(ramp) if ramp.is_done(now) => {
+                self.intensity_ramp = None;
+                self.intensity
+            },
+            Some(ramp) => ramp.value(now),
+            None => self.intensity,
+        }
+    }
+
+    fn update_frame(&mut self, now: u32) {
+        match self.frame {
+            Frame::Stop => {
+                self.frame = self.get_next_frame(now);
+            },
+            Frame::Hold(ref hold) => {
+                if now.wrapping_sub(hold.start) > hold.time {
+                    self.frame = Frame::Stop
+                }
+            },
+            Frame::Fade(ref fade) => {
+                if fade.is_done(now) {
+                    self.output = fade.target;
+                    self.frame = Frame::Stop;
+                } else {
+                    self.output = fade.colour_at(now);
+                }
+            },
+        }
+    }
+
+    fn get_next_frame(&mut self, now: u32) -> Frame {
+        let easing = self.mode.eased_mode().map(|mode| self.easings[mode]).unwrap_or(Easing::Linear);
+        match self.mode {
+            RgbMode::Solid => {
+                Frame::Hold(HoldFrame { start: now, time: 1000 })
+            },
+            RgbMode::Cycle(ref mut index) => {
+                let colours = self.palettes.colours_for(PaletteUse::Cycle);
+                advance_colour_index(index, colours.len());
+                self.output = colours[*index];
+
+                Frame::Hold(HoldFrame { start: now, time: self.delay })
+            },
+            RgbMode::Swirl(ref random, ref mut index, ref mut hold) => {
+                *hold = !*hold;
+
+                if !*hold {
+                    Frame::Hold(HoldFrame { start: now, time: self.delay })
+                } else {
+                    let colours = self.palettes.colours_for(PaletteUse::Swirl);
+                    if *random {
+                        let r = self.rand.rand_u32() as usize;
+                        *index = r % colours.len();
+                    } else {
+                        advance_colour_index(index, colours.len());
+                    }
+
+                    let next = colours[*index];
+                    Frame::new_fade(self.output, next, self.delay.saturating_mul(2), easing, self.fade_space, now)
+                }
+            },
+            RgbMode::Rainbow(ref mut sixth) => {
+                // Fades are chained together so the hue turns at a steady rate, a sixth of the way around every delay
+                advance_colour_index(sixth, 6);
+                let next = Hsv::from_degrees(*sixth as u16 * 60, 255, 255).to_colour();
+                Frame::new_fade(self.output, next, self.delay, easing, ColourSpace::Hsv, now)
+            },
+            RgbMode::Breathe(ref mut rising) => {
+                // Each breath in and out takes half of the delay, and the index colour is picked up on every breath
+                *rising = !*rising;
+                let colour = COLOUR_INDEX[self.index];
+                let next = if *rising { colour } else { colour.scale(self.breathe_floor) };
+                Frame::new_fade(self.output, next, self.delay / 2, easing, ColourSpace::Rgb, now)
+            },
+            RgbMode::Flicker(ref use_index, ref mut dim, ref mut hue) => {
+                // Random targets are smoothed by only moving halfway to them on each step, so the light wavers
+                // instead of jumping around.  The dimming and the hue shift both grow with the flicker setting
+                let amount = self.flicker as i32;
+                let hue_span = amount * FLICKER_HUE_SPAN / 255;
+                let dim_target = self.rand.rand_range(0..amount as u32 + 1) as i32;
+                let hue_target = self.rand.rand_range(0..2 * hue_span as u32 + 1) as i32 - hue_span;
+                *dim += (dim_target - *dim) / 2;
+                *hue += (hue_target - *hue) / 2;
+
+                let base = if *use_index { COLOUR_INDEX[self.index] } else { FLICKER_COLOUR };
+                let mut hsv = base.to_hsv();
+                hsv.hue = hsv.hue.wrapping_add(*hue as u16);
+                hsv.val = (hsv.val as i32 * (255 - *dim) / 255) as u8;
+
+                let step = FLICKER_STEP + self.rand.rand_range(0..FLICKER_STEP);
+                Frame::new_fade(self.output, hsv.to_colour(), step, easing, ColourSpace::Rgb, now)
+            },
+            RgbMode::Sequence(ref mut runner) => {
+                match runner.next(&self.sequence, &mut self.output, &mut self.rand) {
+                    SequenceAction::Fade(time, colour) => Frame::new_fade(self.output, colour, time, easing, self.fade_space, now),
+                    SequenceAction::Hold(time) => Frame::Hold(HoldFrame { start: now, time }),
+                    SequenceAction::Finished => Frame::Hold(HoldFrame { start: now, time: 1000 }),
+                }
+            },
+            RgbMode::Sunrise(ref mut stage) => {
+                // Each stage fades to the next colour over its share of the sunrise time, and the last colour
+                // is held once they've all finished
+                match SUNRISE_STAGES.get(*stage) {
+                    Some((share, colour)) => {
+                        *stage += 1;
+                        let time = (self.sunrise_time as u64 * *share as u64 / 100) as u32;
+                        Frame::new_fade(self.output, *colour, time, easing, ColourSpace::Rgb, now)
+                    },
+                    None => Frame::Hold(HoldFrame { start: now, time: 1000 }),
+                }
+            },
+            RgbMode::Strobe(ref random, ref mut hold) => {
+                *hold = !*hold;
+
+                if !*hold {
+                    self.output = Colour::new(0, 0, 0);
+                    Frame::Hold(HoldFrame { start: now, time: self.delay })
+                } else {
+                    self.output = if *random {
+                        let colours = self.palettes.colours_for(PaletteUse::Strobe);
+                        let colour = colours[self.rand.rand_u32() as usize % colours.len()];
+
+                        // The index follows the colour whenever it's one of the indexed colours, which is always the
+                        // case with the built-in palette
+                        if let Some(index) = COLOUR_INDEX.iter().position(|indexed| *indexed == colour) {
+                            self.index = index;
+                        }
+                        colour
+                    } else {
+                        COLOUR_INDEX[self.index]
+                    };
+                    Frame::Hold(HoldFrame { start: now, time: 70 })
+                }
+            },
+        }
+    }
+
+
+}
+
+fn advance_colour_index(index: &mut usize, max: usize) {
+    *index += 1;
+    if *index >= max {
+        *index = 0;
+    }
+}
+
+impl Colour {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Colour { r, g, b }
+    }
+
+    pub fn scale(self, factor: u8) -> Self {
+        Colour {
+            r: ((self.r as u32) * factor as u32 / 255) as u8,
+            g: ((self.g as u32) * factor as u32 / 255) as u8,
+            b: ((self.b as u32) * factor as u32 / 255) as u8,
+        }
+    }
+
+    pub fn interpolate(self, target: Colour, progress: u32) -> Self {
+        Colour {
+            r: interpolate(self.r, target.r, progress),
+            g: interpolate(self.g, target.g, progress),
+            b: interpolate(self.b, target.b, progress),
+        }
+    }
+
+    // Hue is in degrees (0-359), and saturation and value are 0-255
+    pub fn from_hsv(hue: u16, sat: u8, val: u8) -> Self {
+        Hsv::from_degrees(hue, sat, val).to_colour()
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0 {
+            return Hsv::new(0, 0, max as u8);
+        }
+
+        // Each of the primary and secondary colours is a sixth of the way around the wheel from the next
+        let (sixths, diff) = if max == r {
+            (0, g - b)
+        } else if max == g {
+            (2, b - r)
+        } else {
+            (4, r - g)
+        };
+        let hue = (sixths * 65536 + diff * 65536 / delta) / 6;
+        Hsv::new(hue as u16, (delta * 255 / max) as u8, max as u8)
+    }
+
+    // Parse a colour given as "#rrggbb", "<r> <g> <b>", "hsv <h> <s> <v>", or a name, returning the number of args used
+    pub fn parse(args: &[&str]) -> Option<(Colour, usize)> {
+        let first = args.first()?;
+
+        if let Some(hex) = first.strip_prefix('#') {
+            if hex.len() != 6 || !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+                return None;
+            }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            return Some((Colour::new((value >> 16) as u8, (value >> 8) as u8, value as u8), 1));
+        }
+
+        if *first == "hsv" {
+            if args.len() < 4 {
+                return None;
+            }
+            let hue = lexical_core::parse::<u16>(args[1].as_bytes()).ok()?;
+            let sat = lexical_core::parse::<u8>(args[2].as_bytes()).ok()?;
+            let val = lexical_core::parse::<u8>(args[3].as_bytes()).ok()?;
+            return if hue < 360 { Some((Colour::from_hsv(hue, sat, val), 4)) } else { None };
+        }
+
+        if let Some((_, colour)) = NAMED_COLOURS.iter().find(|(name, _)| name == first) {
+            return Some((*colour, 1));
+        }
+
+        if args.len() >= 3 {
+            let r = lexical_core::parse::<u8>(args[0].as_bytes()).ok()?;
+            let g = lexical_core::parse::<u8>(args[1].as_bytes()).ok()?;
+            let b = lexical_core::parse::<u8>(args[2].as_bytes()).ok()?;
+            return Some((Colour::new(r, g, b), 3));
+        }
+
+        None
+    }
+}
+
+impl Hsv {
+    pub fn new(hue: u16, sat: u8, val: u8) -> Self {
+        Hsv { hue, sat, val }
+    }
+
+    pub fn from_degrees(degrees: u16, sat: u8, val: u8) -> Self {
+        Hsv::new(((degrees % 360) as u32 * 65536 / 360) as u16, sat, val)
+    }
+
+    pub fn to_colour(self) -> Colour {
+        let scaled = self.hue as u32 * 6;
+        let region = scaled >> 16;
+        let remainder = (scaled & 0xffff) >> 8;
+        let (sat, val) = (self.sat as u32, self.val as u32);
+
+        let p = (val * (255 - sat) / 255) as u8;
+        let q = (val * (255 - sat * remainder / 255) / 255) as u8;
+        let t = (val * (255 - sat * (255 - remainder) / 255) / 255) as u8;
+        let v = self.val;
+
+        match region {
+            0 => Colour::new(v, t, p),
+            1 => Colour::new(q, v, p),
+            2 => Colour::new(p, v, t),
+            3 => Colour::new(p, q, v),
+            4 => Colour::new(t, p, v),
+            _ => Colour::new(v, p, q),
+        }
+    }
+
+    // Takes the shortest way around the colour wheel.  Greys have no hue and black has no saturation either, so
+    // those are taken from the other colour
+    pub fn interpolate(self, target: Hsv, progress: u32) -> Self {
+        let (start, target) = (self.fill_from(target), target.fill_from(self));
+        let diff = target.hue.wrapping_sub(start.hue) as i16 as i64;
+        let offset = (diff * progress as i64 + (1 << 15)) >> 16;
+
+        Hsv {
+            hue: start.hue.wrapping_add(offset as u16),
+            sat: interpolate(start.sat, target.sat, progress),
+            val: interpolate(start.val, target.val, progress),
+        }
+    }
+
+    fn fill_from(self, other: Hsv) -> Self {
+        match (self.val, self.sat) {
+            (0, _) => Hsv { val: 0, ..other },
+            (_, 0) => Hsv { hue: other.hue, ..self },
+            _ => self,
+        }
+    }
+}
+
+impl ColourSpace {
+    pub fn parse(name: &str) -> Option<ColourSpace> {
+        match name {
+            "rgb" => Some(ColourSpace::Rgb),
+            "hsv" => Some(ColourSpace::Hsv),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColourSpace::Rgb => "rgb",
+            ColourSpace::Hsv => "hsv",
+        }
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+const NAMED_COLOURS: &[(&str, Colour)] = &[
+    ("black",     Colour { r:   0, g:   0, b:   0 }),
+    ("white",     Colour { r: 255, g: 255, b: 255 }),
+    ("warmwhite", Colour { r: 255, g: 192, b:  96 }),
+    ("red",       Colour { r: 255, g:   0, b:   0 }),
+    ("orange",    Colour { r: 255, g:  64, b:   0 }),
+    ("amber",     Colour { r: 255, g: 128, b:   0 }),
+    ("yellow",    Colour { r: 255, g: 255, b:   0 }),
+    ("lime",      Colour { r: 128, g: 255, b:   0 }),
+    ("green",     Colour { r:   0, g: 255, b:   0 }),
+    ("teal",      Colour { r:   0, g: 255, b: 128 }),
+    ("cyan",      Colour { r:   0, g: 255, b: 255 }),
+    ("azure",     Colour { r:   0, g: 128, b: 255 }),
+    ("blue",      Colour { r:   0, g:   0, b: 255 }),
+    ("violet",    Colour { r: 128, g:   0, b: 255 }),
+    ("purple",    Colour { r: 128, g:   0, b: 128 }),
+    ("magenta",   Colour { r: 255, g:   0, b: 255 }),
+    ("pink",      Colour { r: 255, g:   0, b: 128 }),
+];
+
+// The base colour of the candle mode, which is close to the colour of a real flame
+const FLICKER_COLOUR: Colour = Colour { r: 255, g: 120, b: 24 };
+// The shortest time between changes in the flicker, which is randomly up to twice as long
+const FLICKER_STEP: u32 = 40;
+// The furthest the hue will shift at full flicker, which is about 8 degrees
+const FLICKER_HUE_SPAN: i32 = 1456;
+
+// The colours that the sunrise goes through, and the percentage of the sunrise time taken to fade to each one.
+// The first stage goes straight to black, and the fades are long enough that there are several seconds between
+// each step in the output, so the progress of each fade is kept as a fixed point fraction of its time
+const SUNRISE_STAGES: &[(u32, Colour)] = &[
+    (0, Colour { r: 0, g: 0, b: 0 }),
+    (25, Colour { r: 64, g: 4, b: 0 }),
+    (30, Colour { r: 255, g: 64, b: 0 }),
+    (25, Colour { r: 255, g: 160, b: 64 }),
+    (20, Colour { r: 255, g: 255, b: 255 }),
+];
+
+// The preset colours selected by the index, where the first ones are also the colours of the default palette
+pub(crate) const COLOUR_INDEX: &[Colour] = &[
+    // NOTE these were ported from RGBNode, which doesn't adjust the PWM output for non-linearity, so the colours might not be what's expected unless a non-linear output curve is selected
+    Colour { r: 255, g:   0, b:   0 },
+    Colour { r: 255, g:  32, b:   0 },
+    Colour { r: 255, g:  64, b:   0 },
+    Colour { r: 255, g: 128, b:   0 },
+    Colour { r: 255, g: 255, b:   0 },
+    Colour { r: 128, g: 255, b:   0 },
+    Colour { r:  64, g: 255, b:   0 },
+    Colour { r:  32, g: 255, b:   0 },
+
+    Colour { r:   0, g: 255, b:   0 },
+    Colour { r:   0, g: 255, b:  32 },
+    Colour { r:   0, g: 255, b:  64 },
+    Colour { r:   0, g: 255, b: 128 },
+    Colour { r:   0, g: 255, b: 255 },
+    Colour { r:   0, g: 128, b: 255 },
+    Colour { r:   0, g:  64, b: 255 },
+    Colour { r:   0, g:  32, b: 255 },
+
+    Colour { r:   0, g:   0, b: 255 },
+    Colour { r:  32, g:   0, b: 255 },
+    Colour { r:  64, g:   0, b: 255 },
+    Colour { r: 128, g:   0, b: 255 },
+    Colour { r: 255, g:   0, b: 255 },
+    Colour { r: 255, g:   0, b: 128 },
+    Colour { r: 255, g:   0, b:  64 },
+    Colour { r: 255, g:   0, b:  32 },
+
+    Colour { r: 255, g: 255, b: 255 },
+    Colour { r: 255, g: 255, b: 128 },
+    Colour { r: 255, g: 255, b:  64 },
+    Colour { r: 255, g: 192, b:   0 },
+    Colour { r:  16, g: 255, b:   0 },
+    Colour { r:   0, g:  80, b: 255 }
+];
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence::{ Step };
+
+    struct NullRgb;
+
+    #[derive(Default)]
+    struct RecordRgb {
+        enabled: bool,
+        colour: Option<Colour>,
+    }
+
+    impl RgbDevice for RecordRgb {
+        fn enable(&mut self) { self.enabled = true; }
+        fn disable(&mut self) { self.enabled = false; }
+        fn set_colour(&mut self, val: Colour) { self.colour = Some(val); }
+        fn curve(&self) -> OutputCurve { OutputCurve::Linear }
+        fn set_curve(&mut self, _curve: OutputCurve) { }
+        fn calibration(&self) -> Colour { Colour::new(255, 255, 255) }
+        fn set_calibration(&mut self, _calibration: Colour) { }
+    }
+
+    impl RgbDevice for NullRgb {
+        fn enable(&mut self) { }
+        fn disable(&mut self) { }
+        fn set_colour(&mut self, _val: Colour) { }
+        fn curve(&self) -> OutputCurve { OutputCurve::Linear }
+        fn set_curve(&mut self, _curve: OutputCurve) { }
+        fn calibration(&self) -> Colour { Colour::new(255, 255, 255) }
+        fn set_calibration(&mut self, _calibration: Colour) { }
+    }
+
+    #[test]
+    fn test_colour_scale() {
+        let colour = Colour::new(255, 128, 0);
+        assert_eq!(colour.scale(255), colour);
+        assert_eq!(colour.scale(0), Colour::new(0, 0, 0));
+        assert_eq!(colour.scale(128), Colour::new(128, 64, 0));
+    }
+
+    #[test]
+    fn test_colour_from_hsv() {
+        assert_eq!(Colour::from_hsv(0, 255, 255), Colour::new(255, 0, 0));
+        assert_eq!(Colour::from_hsv(120, 255, 255), Colour::new(0, 255, 0));
+        assert_eq!(Colour::from_hsv(240, 255, 128), Colour::new(0, 0, 128));
+        assert_eq!(Colour::from_hsv(30, 255, 255), Colour::new(255, 127, 0));
+        assert_eq!(Colour::from_hsv(200, 0, 200), Colour::new(200, 200, 200));
+    }
+
+    #[test]
+    fn test_colour_to_hsv() {
+        assert_eq!(Colour::new(255, 0, 0).to_hsv(), Hsv::new(0, 255, 255));
+        assert_eq!(Colour::new(0, 0, 128).to_hsv(), Hsv::new(43690, 255, 128));
+        assert_eq!(Colour::new(255, 0, 128).to_hsv().to_colour(), Colour::new(255, 0, 128));
+        assert_eq!(Colour::new(100, 100, 100).to_hsv(), Hsv::new(0, 0, 100));
+        for colour in COLOUR_INDEX {
+            let converted = colour.to_hsv().to_colour();
+            assert!((converted.r as i32 - colour.r as i32).abs() <= 1);
+            assert!((converted.g as i32 - colour.g as i32).abs() <= 1);
+            assert!((converted.b as i32 - colour.b as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_hue_fade() {
+        let red = Colour::new(255, 0, 0);
+        let blue = Colour::new(0, 0, 255);
+        let fade = FadeFrame { start: red, target: blue, begin: 0, time: 100, easing: Easing::Linear, space: ColourSpace::Hsv };
+
+        // Red to blue goes backwards around the wheel through magenta, at full brightness
+        assert_eq!(fade.colour_at(0), red);
+        assert_eq!(fade.colour_at(50), Colour::new(255, 0, 255));
+        assert_eq!(fade.colour_at(100), blue);
+
+        let fade = FadeFrame { start: Colour::new(0, 0, 0), target: Colour::new(0, 255, 0), begin: 0, time: 100, easing: Easing::Linear, space: ColourSpace::Hsv };
+        assert_eq!(fade.colour_at(50), Colour::new(0, 128, 0));
+    }
+
+    #[test]
+    fn test_colour_parse() {
+        assert_eq!(Colour::parse(&["#ff8800"]), Some((Colour::new(0xff, 0x88, 0x00), 1)));
+        assert_eq!(Colour::parse(&["10", "20", "30", "fade"]), Some((Colour::new(10, 20, 30), 3)));
+        assert_eq!(Colour::parse(&["hsv", "240", "255", "255"]), Some((Colour::new(0, 0, 255), 4)));
+        assert_eq!(Colour::parse(&["cyan"]), Some((Colour::new(0, 255, 255), 1)));
+        assert_eq!(Colour::parse(&["#ff88"]), None);
+        assert_eq!(Colour::parse(&["#gg8800"]), None);
+        assert_eq!(Colour::parse(&["#+f8800"]), None);
+        assert_eq!(Colour::parse(&["hsv", "360", "255", "255"]), None);
+        assert_eq!(Colour::parse(&["10", "20"]), None);
+        assert_eq!(Colour::parse(&["bogus"]), None);
+    }
+
+    #[test]
+    fn test_index_wraps() {
+        let mut engine = RgbEngine::new(0);
+        engine.index(Some(COLOUR_INDEX.len() - 1));
+        engine.index_up();
+        assert_eq!(engine.index(None), 0);
+        engine.index_down();
+        assert_eq!(engine.index(None), COLOUR_INDEX.len() - 1);
+    }
+
+    #[test]
+    fn test_fade_channels_arrive_together() {
+        let start = Colour::new(0, 255, 100);
+        let target = Colour::new(255, 0, 110);
+        let fade = FadeFrame { start, target, begin: 1000, time: 10, easing: Easing::Linear, space: ColourSpace::Rgb };
+
+        assert_eq!(fade.colour_at(1000), start);
+        assert_eq!(fade.colour_at(1005), Colour::new(128, 128, 105));
+        assert_eq!(fade.colour_at(1009), Colour::new(229, 26, 109));
+        assert_eq!(fade.colour_at(1010), target);
+        assert!(!fade.is_done(1009));
+        assert!(fade.is_done(1010));
+    }
+
+    #[test]
+    fn test_fade_with_slow_loop() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(0, 0, 0));
+        engine.handle_animation(&mut NullRgb, 0);
+
+        // Even if the loop only runs occasionally, the output follows the fade instead of lagging behind
+        engine.fade_colour(Colour::new(200, 100, 0), 1000, 0);
+        engine.handle_animation(&mut NullRgb, 250);
+        assert_eq!(engine.get_colour(), Colour::new(50, 25, 0));
+        engine.handle_animation(&mut NullRgb, 900);
+        assert_eq!(engine.get_colour(), Colour::new(180, 90, 0));
+        engine.handle_animation(&mut NullRgb, 1000);
+        assert_eq!(engine.get_colour(), Colour::new(200, 100, 0));
+    }
+
+    #[test]
+    fn test_eased_fade_colour() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(0, 0, 0));
+        engine.transition_easing(Some(Easing::EaseIn));
+        engine.handle_animation(&mut NullRgb, 0);
+
+        engine.fade_colour(Colour::new(200, 100, 0), 1000, 0);
+        engine.handle_animation(&mut NullRgb, 500);
+        assert_eq!(engine.get_colour(), Colour::new(50, 25, 0));
+        engine.handle_animation(&mut NullRgb, 1000);
+        assert_eq!(engine.get_colour(), Colour::new(200, 100, 0));
+    }
+
+    #[test]
+    fn test_instant_colour_cancels_fade() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(0, 0, 0));
+        engine.handle_animation(&mut NullRgb, 0);
+
+        engine.fade_colour(Colour::new(255, 0, 0), 1000, 0);
+        engine.handle_animation(&mut NullRgb, 100);
+        engine.fade_colour(Colour::new(0, 0, 255), 0, 100);
+        engine.handle_animation(&mut NullRgb, 200);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 255));
+        engine.handle_animation(&mut NullRgb, 1300);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 255));
+
+        engine.fade_colour(Colour::new(255, 0, 0), 1000, 1300);
+        engine.handle_animation(&mut NullRgb, 1400);
+        engine.set_colour(Colour::new(0, 255, 0));
+        engine.handle_animation(&mut NullRgb, 2400);
+        assert_eq!(engine.get_colour(), Colour::new(0, 255, 0));
+    }
+
+    #[test]
+    fn test_easing_per_mode() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(600));
+        engine.easing(1, Some(Easing::EaseIn));
+        engine.set_colour(Colour::new(255, 0, 0));
+        engine.rainbow_mode();
+        engine.force_update(0);
+
+        engine.handle_animation(&mut NullRgb, 300);
+        assert_eq!(engine.get_colour(), Colour::new(255, 64, 0));
+        assert_eq!(engine.mode().eased_mode().map(|mode| EASED_MODES[mode]), Some("rainbow"));
+        assert_eq!(engine.easing(0, None), Easing::Linear);
+    }
+
+    #[test]
+    fn test_ramp() {
+        let ramp = Ramp::new(100, 0, 1000, u32::MAX - 100);
+        assert_eq!(ramp.value(u32::MAX - 100), 100);
+        assert_eq!(ramp.value(399), 50);
+        assert_eq!(ramp.value(899), 0);
+        assert!(ramp.is_done(899));
+        assert!(!ramp.is_done(898));
+    }
+
+    #[test]
+    fn test_fade_intensity() {
+        let mut engine = RgbEngine::new(0);
+        engine.fade_intensity(55, 200, 0);
+        assert_eq!(engine.intensity(None), 55);
+        assert_eq!(engine.current_intensity(100), 155);
+        engine.fade_intensity(255, 100, 100);
+        assert_eq!(engine.current_intensity(150), 205);
+        assert_eq!(engine.current_intensity(200), 255);
+        assert!(engine.intensity_ramp.is_none());
+    }
+
+    #[test]
+    fn test_solid_fade_colour() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(0, 0, 0));
+        engine.handle_animation(&mut NullRgb, 0);
+
+        engine.index(Some(COLOUR_INDEX.len() + 8));
+        engine.show_index(100, 0);
+        engine.handle_animation(&mut NullRgb, 50);
+        assert_ne!(engine.get_colour(), COLOUR_INDEX[8]);
+        engine.handle_animation(&mut NullRgb, 100);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[8]);
+    }
+
+    #[test]
+    fn test_power_fade() {
+        let mut dev = RecordRgb::default();
+        let mut engine = RgbEngine::new(0);
+        engine.solid_mode();
+        engine.power_transition(Some(100));
+
+        engine.power(&mut dev, true, 0);
+        assert!(dev.enabled);
+        assert_eq!(dev.colour, Some(Colour::new(0, 0, 0)));
+        engine.handle_animation(&mut dev, 50);
+        assert_eq!(dev.colour, Some(Colour::new(128, 128, 128)));
+        engine.handle_animation(&mut dev, 100);
+        assert_eq!(dev.colour, Some(Colour::new(255, 255, 255)));
+
+        engine.power(&mut dev, false, 200);
+        assert!(!engine.enabled());
+        engine.handle_animation(&mut dev, 275);
+        assert!(dev.enabled);
+        assert_eq!(dev.colour, Some(Colour::new(64, 64, 64)));
+
+        // Turning back on part way through starts from the current level
+        engine.power(&mut dev, true, 275);
+        engine.handle_animation(&mut dev, 325);
+        assert_eq!(dev.colour, Some(Colour::new(160, 160, 160)));
+
+        engine.power(&mut dev, false, 400);
+        engine.handle_animation(&mut dev, 500);
+        assert!(!dev.enabled);
+        assert_eq!(dev.colour, Some(Colour::new(0, 0, 0)));
+        engine.handle_animation(&mut dev, 600);
+        assert!(!dev.enabled);
+    }
+
+    #[test]
+    fn test_cycle_mode_timing() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        engine.cycle_mode();
+
+        engine.handle_animation(&mut NullRgb, 0);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+        engine.handle_animation(&mut NullRgb, 100);
+        engine.handle_animation(&mut NullRgb, 100);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+        engine.handle_animation(&mut NullRgb, 101);
+        engine.handle_animation(&mut NullRgb, 101);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[2]);
+    }
+
+    #[test]
+    fn test_swirl_fade_reaches_target() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        engine.swirl_mode(false);
+        engine.set_colour(Colour::new(0, 0, 0));
+
+        engine.handle_animation(&mut NullRgb, 0);
+        for now in 1..200 {
+            engine.handle_animation(&mut NullRgb, now);
+            assert_ne!(engine.get_colour(), COLOUR_INDEX[1]);
+        }
+        engine.handle_animation(&mut NullRgb, 200);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+    }
+
+    #[test]
+    fn test_rainbow_turns_steadily() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(600));
+        engine.set_colour(Colour::new(255, 0, 0));
+        engine.rainbow_mode();
+        engine.force_update(0);
+
+        engine.handle_animation(&mut NullRgb, 300);
+        assert_eq!(engine.get_colour(), Colour::new(255, 127, 0));
+        engine.handle_animation(&mut NullRgb, 600);
+        assert_eq!(engine.get_colour(), Colour::new(255, 255, 0));
+        engine.handle_animation(&mut NullRgb, 600);
+        engine.handle_animation(&mut NullRgb, 900);
+        assert_eq!(engine.get_colour(), Colour::new(127, 255, 0));
+        for now in (1000..=3600).step_by(100) {
+            engine.handle_animation(&mut NullRgb, now);
+            let hsv = engine.get_colour().to_hsv();
+            assert!(hsv.sat >= 254 && hsv.val == 255);
+        }
+    }
+
+    #[test]
+    fn test_breathe_between_floor_and_colour() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(1000));
+        engine.index(Some(16));
+        engine.breathe_floor(Some(64));
+        engine.set_colour(Colour::new(0, 0, 64));
+        engine.breathe_mode();
+        engine.force_update(0);
+
+        engine.handle_animation(&mut NullRgb, 250);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 160));
+        engine.handle_animation(&mut NullRgb, 500);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 255));
+        engine.handle_animation(&mut NullRgb, 500);
+        engine.handle_animation(&mut NullRgb, 1000);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 64));
+        engine.handle_animation(&mut NullRgb, 1000);
+        engine.handle_animation(&mut NullRgb, 1500);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 255));
+    }
+
+    #[test]
+    fn test_flicker_stays_near_base() {
+        let mut engine = RgbEngine::new(1234);
+        engine.power(&mut NullRgb, true, 0);
+        engine.flicker(Some(255));
+        engine.set_colour(FLICKER_COLOUR);
+        engine.flicker_mode(false);
+        engine.force_update(0);
+
+        let (mut darkest, mut brightest) = (255, 0);
+        for now in (0..10_000).step_by(10) {
+            engine.handle_animation(&mut NullRgb, now);
+            let colour = engine.get_colour();
+            assert_eq!(colour.r.max(colour.g).max(colour.b), colour.r);
+            assert!(colour.g < colour.r && colour.b < colour.g);
+            darkest = darkest.min(colour.r);
+            brightest = brightest.max(colour.r);
+        }
+        assert!(darkest < 160 && brightest > 220);
+
+        // With no flicker, the colour is steady
+        engine.flicker(Some(0));
+        engine.flicker_mode(true);
+        engine.index(Some(16));
+        engine.force_update(10_000);
+        for now in (10_000..12_000).step_by(10) {
+            engine.handle_animation(&mut NullRgb, now);
+        }
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 255));
+    }
+
+    #[test]
+    fn test_swirl_uses_palette() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        let palettes = engine.palettes_mut();
+        let slot = palettes.create("two").unwrap();
+        palettes.set_colour(slot, 0, Colour::new(10, 20, 30)).unwrap();
+        palettes.set_colour(slot, 1, Colour::new(40, 50, 60)).unwrap();
+        palettes.select(PaletteUse::Swirl, slot);
+        engine.swirl_mode(false);
+
+        // Each colour is faded to over twice the delay, and then held for the delay
+        for now in (0..=600).step_by(10) {
+            engine.handle_animation(&mut NullRgb, now);
+            match now {
+                250 => assert_eq!(engine.get_colour(), Colour::new(40, 50, 60)),
+                550 => assert_eq!(engine.get_colour(), Colour::new(10, 20, 30)),
+                _ => { },
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_strobe_follows_index() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        engine.strobe_mode(true);
+
+        let mut flashes = 0;
+        for now in (0..2000).step_by(10) {
+            engine.handle_animation(&mut NullRgb, now);
+            if engine.get_colour() != Colour::new(0, 0, 0) {
+                assert_eq!(engine.get_colour(), COLOUR_INDEX[engine.index(None)]);
+                flashes += 1;
+            }
+        }
+        assert!(flashes > 0);
+
+        // Colours that aren't in the index leave it alone
+        let palettes = engine.palettes_mut();
+        let slot = palettes.create("mixed").unwrap();
+        palettes.set_colour(slot, 0, Colour::new(10, 20, 30)).unwrap();
+        palettes.select(PaletteUse::Strobe, slot);
+        let index = engine.index(Some(5));
+        engine.force_update(2000);
+        engine.handle_animation(&mut NullRgb, 2000);
+        assert_eq!(engine.get_colour(), Colour::new(10, 20, 30));
+        assert_eq!(engine.index(None), index);
+    }
+
+    #[test]
+    fn test_sequence_mode() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        for step in &[Step::Set(Colour::new(0, 0, 0)), Step::Fade(100, Colour::new(200, 0, 0)), Step::Hold(100), Step::Loop(0, 0)] {
+            engine.sequence_mut().push(*step).unwrap();
+        }
+        engine.sequence_mode();
+        engine.force_update(0);
+
+        engine.handle_animation(&mut NullRgb, 50);
+        assert_eq!(engine.get_colour(), Colour::new(100, 0, 0));
+        engine.handle_animation(&mut NullRgb, 100);
+        assert_eq!(engine.get_colour(), Colour::new(200, 0, 0));
+        engine.handle_animation(&mut NullRgb, 150);
+        engine.handle_animation(&mut NullRgb, 251);
+        engine.handle_animation(&mut NullRgb, 252);
+        assert_eq!(engine.get_colour(), Colour::new(0, 0, 0));
+        engine.handle_animation(&mut NullRgb, 302);
+        assert_eq!(engine.get_colour(), Colour::new(100, 0, 0));
+    }
+
+    #[test]
+    fn test_sleep_timer() {
+        let mut engine = RgbEngine::new(0);
+        let mut dev = RecordRgb::default();
+        engine.power(&mut dev, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(200, 200, 200));
+        engine.intensity(Some(128));
+
+        engine.sleep(1000, 0);
+        engine.handle_animation(&mut dev, 500);
+        assert_eq!(dev.colour, Some(Colour::new(50, 50, 50)));
+        assert_eq!(engine.sleep_remaining(500), Some(500));
+        engine.handle_animation(&mut dev, 1000);
+        assert!(!dev.enabled && !engine.enabled());
+        assert_eq!(engine.sleep_remaining(1000), None);
+        assert_eq!(engine.intensity(None), 128);
+
+        // Turning the power on or off cancels the timer
+        engine.power(&mut dev, true, 2000);
+        engine.sleep(1000, 2000);
+        engine.power(&mut dev, true, 2500);
+        engine.handle_animation(&mut dev, 3500);
+        assert!(dev.enabled);
+        assert_eq!(dev.colour, Some(Colour::new(100, 100, 100)));
+    }
+
+    #[test]
+    fn test_sunrise() {
+        let mut engine = RgbEngine::new(0);
+        let mut dev = RecordRgb::default();
+        engine.power(&mut dev, true, 0);
+        engine.intensity(Some(40));
+        engine.sunrise_time(Some(100_000));
+        engine.sunrise_mode();
+        engine.force_update(0);
+        assert_eq!(engine.mode().name(), "sunrise");
+
+        // Starts from off even if something else was showing
+        engine.handle_animation(&mut dev, 0);
+        assert_eq!(dev.colour, Some(Colour::new(0, 0, 0)));
+        engine.handle_animation(&mut dev, 0);
+        engine.handle_animation(&mut dev, 12_500);
+        assert_eq!(dev.colour, Some(Colour::new(32, 2, 0)));
+
+        engine.handle_animation(&mut dev, 25_000);
+        engine.handle_animation(&mut dev, 25_000);
+        engine.handle_animation(&mut dev, 40_000);
+        assert_eq!(dev.colour, Some(Colour::new(160, 34, 0)));
+
+        for now in &[55_000, 55_000, 80_000, 80_000, 100_000, 100_000, 200_000] {
+            engine.handle_animation(&mut dev, *now);
+        }
+        assert_eq!(dev.colour, Some(Colour::new(255, 255, 255)));
+        assert_eq!(engine.intensity(None), 40);
+
+        engine.solid_mode();
+        engine.handle_animation(&mut dev, 200_000);
+        assert_eq!(dev.colour, Some(Colour::new(40, 40, 40)));
+    }
+
+    #[test]
+    fn test_sleep_with_power_fade() {
+        let mut engine = RgbEngine::new(0);
+        let mut dev = RecordRgb::default();
+        engine.power(&mut dev, true, 0);
+        engine.solid_mode();
+        engine.set_colour(Colour::new(200, 200, 200));
+        engine.power_transition(Some(1000));
+
+        // The output stays off at the end, instead of coming back on to fade out again
+        engine.sleep(1000, 0);
+        engine.handle_animation(&mut dev, 900);
+        assert_eq!(dev.colour, Some(Colour::new(20, 20, 20)));
+        engine.handle_animation(&mut dev, 1000);
+        assert!(!dev.enabled && !engine.enabled());
+        engine.handle_animation(&mut dev, 1100);
+        assert_eq!(dev.colour, Some(Colour::new(20, 20, 20)));
+
+        // Turning on again fades in from off
+        engine.power(&mut dev, true, 2000);
+        engine.handle_animation(&mut dev, 2500);
+        assert!(dev.enabled);
+        assert_eq!(dev.colour, Some(Colour::new(100, 100, 100)));
+    }
+
+    #[test]
+    fn test_hold_across_rollover() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        engine.cycle_mode();
+
+        let start = u32::MAX - 50;
+        engine.handle_animation(&mut NullRgb, start);
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+        engine.handle_animation(&mut NullRgb, start.wrapping_add(100));
+        engine.handle_animation(&mut NullRgb, start.wrapping_add(100));
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+        engine.handle_animation(&mut NullRgb, start.wrapping_add(101));
+        engine.handle_animation(&mut NullRgb, start.wrapping_add(101));
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[2]);
+    }
+
+    #[test]
+    fn test_fade_across_rollover() {
+        let mut engine = RgbEngine::new(0);
+        engine.power(&mut NullRgb, true, 0);
+        engine.delay(Some(100));
+        engine.swirl_mode(false);
+        engine.set_colour(Colour::new(0, 0, 0));
+
+        let start = u32::MAX - 100;
+        engine.handle_animation(&mut NullRgb, start);
+        for step in 1..200 {
+            engine.handle_animation(&mut NullRgb, start.wrapping_add(step));
+            assert_ne!(engine.get_colour(), COLOUR_INDEX[1]);
+        }
+        engine.handle_animation(&mut NullRgb, start.wrapping_add(200));
+        assert_eq!(engine.get_colour(), COLOUR_INDEX[1]);
+    }
+}
@@ -0,0 +1,351 @@
+
+use core::fmt;
+
+use oorandom::Rand32;
+
+use crate::rgb::{ Colour };
+
+
+pub const SEQUENCE_MAX_STEPS: usize = 32;
+pub const RANDOM_MAX_CHOICES: usize = 6;
+
+// The size of a sequence when encoded for saving to flash
+pub const SEQUENCE_ENCODED_SIZE: usize = 2 + SEQUENCE_MAX_STEPS * STEP_ENCODED_SIZE;
+const STEP_ENCODED_SIZE: usize = 8;
+const SEQUENCE_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SequenceError;
+
+// Loops and random choices jump to other steps by their number, counting from 0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Step {
+    Set(Colour),
+    Fade(u32, Colour),
+    Hold(u32),
+    // Jump to a step, either forever (0) or this many more times before carrying on
+    Loop(u8, u8),
+    // Jump to one of up to six steps at random
+    Random([u8; RANDOM_MAX_CHOICES], u8),
+}
+
+impl Step {
+    // Parse a step given as "set <colour>", "fade <ms> <colour>", "hold <ms>", "loop <step> [count]", or
+    // "random <step> <step> ..."
+    pub fn parse(args: &[&str]) -> Option<Step> {
+        match args {
+            ["set", colour @ ..] => Some(Step::Set(parse_whole_colour(colour)?)),
+            ["fade", time, colour @ ..] => Some(Step::Fade(parse_number(time)?, parse_whole_colour(colour)?)),
+            ["hold", time] => Some(Step::Hold(parse_number(time)?)),
+            ["loop", step] => Some(Step::Loop(parse_number(step)?, 0)),
+            ["loop", step, count] => Some(Step::Loop(parse_number(step)?, parse_number(count)?)),
+            ["random", choices @ ..] if !choices.is_empty() && choices.len() <= RANDOM_MAX_CHOICES => {
+                let mut steps = [0; RANDOM_MAX_CHOICES];
+                for (step, choice) in steps.iter_mut().zip(choices) {
+                    *step = parse_number(choice)?;
+                }
+                Some(Step::Random(steps, choices.len() as u8))
+            },
+            _ => None,
+        }
+    }
+
+    fn encode(&self, data: &mut [u8]) {
+        match self {
+            Step::Set(colour) => {
+                data[0] = 1;
+                data[1..4].copy_from_slice(&[colour.r, colour.g, colour.b]);
+            },
+            Step::Fade(time, colour) => {
+                data[0] = 2;
+                data[1..4].copy_from_slice(&[colour.r, colour.g, colour.b]);
+                data[4..8].copy_from_slice(&time.to_le_bytes());
+            },
+            Step::Hold(time) => {
+                data[0] = 3;
+                data[4..8].copy_from_slice(&time.to_le_bytes());
+            },
+            Step::Loop(step, count) => {
+                data[0] = 4;
+                data[1] = *step;
+                data[2] = *count;
+            },
+            Step::Random(steps, len) => {
+                data[0] = 5;
+                data[1] = *len;
+                data[2..8].copy_from_slice(steps);
+            },
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Step> {
+        let time = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        match data[0] {
+            1 => Some(Step::Set(Colour::new(data[1], data[2], data[3]))),
+            2 => Some(Step::Fade(time, Colour::new(data[1], data[2], data[3]))),
+            3 => Some(Step::Hold(time)),
+            4 => Some(Step::Loop(data[1], data[2])),
+            5 if data[1] >= 1 && data[1] as usize <= RANDOM_MAX_CHOICES => {
+                let mut steps = [0; RANDOM_MAX_CHOICES];
+                steps.copy_from_slice(&data[2..8]);
+                Some(Step::Random(steps, data[1]))
+            },
+            _ => None,
+        }
+    }
+}
+
+// Steps are printed in the same form that they're parsed from
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Set(colour) => write!(f, "set {}", colour),
+            Step::Fade(time, colour) => write!(f, "fade {} {}", time, colour),
+            Step::Hold(time) => write!(f, "hold {}", time),
+            Step::Loop(step, 0) => write!(f, "loop {}", step),
+            Step::Loop(step, count) => write!(f, "loop {} {}", step, count),
+            Step::Random(steps, len) => {
+                write!(f, "random")?;
+                for step in &steps[..*len as usize] {
+                    write!(f, " {}", step)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+fn parse_number<N: lexical_core::FromLexical>(arg: &str) -> Option<N> {
+    lexical_core::parse::<N>(arg.as_bytes()).ok()
+}
+
+fn parse_whole_colour(args: &[&str]) -> Option<Colour> {
+    match Colour::parse(args) {
+        Some((colour, used)) if used == args.len() => Some(colour),
+        _ => None,
+    }
+}
+
+
+pub struct Sequence {
+    steps: [Step; SEQUENCE_MAX_STEPS],
+    len: usize,
+    modified: bool,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence {
+            steps: [Step::Hold(0); SEQUENCE_MAX_STEPS],
+            len: 0,
+            modified: false,
+        }
+    }
+}
+
+impl Sequence {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps[..self.len]
+    }
+
+    pub fn push(&mut self, step: Step) -> Result<(), SequenceError> {
+        if self.len >= SEQUENCE_MAX_STEPS {
+            return Err(SequenceError);
+        }
+        self.steps[self.len] = step;
+        self.len += 1;
+        self.modified = true;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.modified = true;
+    }
+
+    // Whether the sequence has changed since it was last saved or loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    // Layout: version, number of steps, and then each step as its kind followed by up to 7 bytes of arguments
+    pub fn encode(&self, data: &mut [u8; SEQUENCE_ENCODED_SIZE]) {
+        *data = [0; SEQUENCE_ENCODED_SIZE];
+        data[0] = SEQUENCE_VERSION;
+        data[1] = self.len as u8;
+        for (step, chunk) in self.steps().iter().zip(data[2..].chunks_mut(STEP_ENCODED_SIZE)) {
+            step.encode(chunk);
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Sequence> {
+        if data.len() != SEQUENCE_ENCODED_SIZE || data[0] != SEQUENCE_VERSION || data[1] as usize > SEQUENCE_MAX_STEPS {
+            return None;
+        }
+
+        let mut sequence = Sequence::default();
+        for chunk in data[2..].chunks(STEP_ENCODED_SIZE).take(data[1] as usize) {
+            sequence.push(Step::decode(chunk)?).ok()?;
+        }
+        sequence.modified = false;
+        Some(sequence)
+    }
+}
+
+
+// What the runner asks the engine to do next
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SequenceAction {
+    Fade(u32, Colour),
+    Hold(u32),
+    // The sequence has run off the end, so the last colour stays on
+    Finished,
+}
+
+// Keeps track of where a sequence is up to, and how many times each loop step has jumped
+pub struct SequenceRunner {
+    position: usize,
+    counts: [u8; SEQUENCE_MAX_STEPS],
+}
+
+impl Default for SequenceRunner {
+    fn default() -> Self {
+        SequenceRunner {
+            position: 0,
+            counts: [0; SEQUENCE_MAX_STEPS],
+        }
+    }
+}
+
+impl SequenceRunner {
+    // Run the sequence until it reaches a step that takes time.  Set steps change the output immediately, and a
+    // sequence that loops without taking any time is held for a moment, so it can't stop the main loop
+    pub fn next(&mut self, sequence: &Sequence, output: &mut Colour, rand: &mut Rand32) -> SequenceAction {
+        for _ in 0..SEQUENCE_MAX_STEPS {
+            let step = match sequence.steps().get(self.position) {
+                Some(step) => *step,
+                None => return SequenceAction::Finished,
+            };
+            self.position += 1;
+
+            match step {
+                Step::Set(colour) => *output = colour,
+                Step::Fade(time, colour) => return SequenceAction::Fade(time, colour),
+                Step::Hold(time) => return SequenceAction::Hold(time),
+                Step::Loop(target, 0) => self.position = target as usize,
+                Step::Loop(target, count) => {
+                    let jumps = &mut self.counts[self.position - 1];
+                    if *jumps < count {
+                        *jumps += 1;
+                        self.position = target as usize;
+                    } else {
+                        *jumps = 0;
+                    }
+                },
+                Step::Random(targets, len) => {
+                    self.position = targets[rand.rand_range(0..len as u32) as usize] as usize;
+                },
+            }
+        }
+        SequenceAction::Hold(1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_sequence(lines: &[&str]) -> Sequence {
+        let mut sequence = Sequence::default();
+        for line in lines {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            sequence.push(Step::parse(&args).unwrap()).unwrap();
+        }
+        sequence
+    }
+
+    #[test]
+    fn test_step_parse() {
+        assert_eq!(Step::parse(&["set", "#ff0000"]), Some(Step::Set(Colour::new(255, 0, 0))));
+        assert_eq!(Step::parse(&["fade", "500", "hsv", "120", "255", "255"]), Some(Step::Fade(500, Colour::new(0, 255, 0))));
+        assert_eq!(Step::parse(&["hold", "1000"]), Some(Step::Hold(1000)));
+        assert_eq!(Step::parse(&["loop", "2", "3"]), Some(Step::Loop(2, 3)));
+        assert_eq!(Step::parse(&["random", "1", "4"]), Some(Step::Random([1, 4, 0, 0, 0, 0], 2)));
+
+        assert_eq!(Step::parse(&["set", "#ff0000", "extra"]), None);
+        assert_eq!(Step::parse(&["fade", "red"]), None);
+        assert_eq!(Step::parse(&["hold", "-1"]), None);
+        assert_eq!(Step::parse(&["loop", "300"]), None);
+        assert_eq!(Step::parse(&["random"]), None);
+        assert_eq!(Step::parse(&["random", "1", "2", "3", "4", "5", "6", "7"]), None);
+        assert_eq!(Step::parse(&["bogus"]), None);
+    }
+
+    #[test]
+    fn test_step_display() {
+        for line in &["set #ff0000", "fade 500 #00ff00", "hold 1000", "loop 0", "loop 2 3", "random 1 4 5"] {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(format!("{}", Step::parse(&args).unwrap()), *line);
+        }
+    }
+
+    #[test]
+    fn test_sequence_encoding() {
+        let sequence = parse_sequence(&["set #ff0000", "fade 70000 #0000ff", "hold 5", "loop 1 3", "random 0 2 3"]);
+        let mut data = [0; SEQUENCE_ENCODED_SIZE];
+        sequence.encode(&mut data);
+
+        let decoded = Sequence::decode(&data).unwrap();
+        assert_eq!(decoded.steps(), sequence.steps());
+        assert!(!decoded.is_modified());
+
+        data[2] = 9;
+        assert!(Sequence::decode(&data).is_none());
+    }
+
+    #[test]
+    fn test_runner_steps_and_loops() {
+        let sequence = parse_sequence(&["set #ff0000", "hold 100", "fade 200 #0000ff", "loop 1 1", "hold 50"]);
+        let mut runner = SequenceRunner::default();
+        let mut output = Colour::new(0, 0, 0);
+        let mut rand = Rand32::new(0);
+
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Hold(100));
+        assert_eq!(output, Colour::new(255, 0, 0));
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Fade(200, Colour::new(0, 0, 255)));
+        // The loop jumps back once, and then carries on
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Hold(100));
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Fade(200, Colour::new(0, 0, 255)));
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Hold(50));
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Finished);
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Finished);
+    }
+
+    #[test]
+    fn test_runner_random_and_forever() {
+        let sequence = parse_sequence(&["random 1 3", "hold 10", "loop 0", "hold 20", "loop 0"]);
+        let mut runner = SequenceRunner::default();
+        let mut output = Colour::new(0, 0, 0);
+        let mut rand = Rand32::new(0);
+
+        let mut seen = [false; 2];
+        for _ in 0..20 {
+            match runner.next(&sequence, &mut output, &mut rand) {
+                SequenceAction::Hold(10) => seen[0] = true,
+                SequenceAction::Hold(20) => seen[1] = true,
+                action => panic!("unexpected {:?}", action),
+            }
+        }
+        assert_eq!(seen, [true, true]);
+
+        // A loop with nothing that takes time doesn't run forever
+        let sequence = parse_sequence(&["set red", "loop 0"]);
+        let mut runner = SequenceRunner::default();
+        assert_eq!(runner.next(&sequence, &mut output, &mut rand), SequenceAction::Hold(1));
+    }
+}
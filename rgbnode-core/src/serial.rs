@@ -0,0 +1,83 @@
+
+pub trait SerialInterface {
+    fn poll_read(&mut self, input: &mut InputLine) -> bool;
+    fn write(&mut self, string: &[u8]);
+}
+
+pub struct InputLine {
+    pub term: usize,
+    pub length: usize,
+    pub data: [u8; 128]
+}
+
+impl Default for InputLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputLine {
+    pub fn new() -> InputLine {
+        InputLine {
+            term: 0,
+            length: 0,
+            data: [0u8; 128]
+        }
+    }
+
+    pub fn push_data(&mut self, s: &[u8]) -> bool {
+        let start = self.length;
+
+        for ch in s {
+            self.data[self.length] = *ch;
+            self.length += 1;
+        }
+
+        for i in start..self.length {
+            if self.data[i] == b'\n' {
+                self.term = i + 1;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.term = 0;
+        self.length = 0;
+    }
+
+    pub fn discard(&mut self) {
+        let diff = self.length - self.term;
+        for i in 0..diff {
+            self.data[i] = self.data[self.term + i];
+        }
+
+        self.length = diff;
+        self.term = 0;
+    }
+
+    pub fn to_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(&self.data[0..self.term])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_line_waits_for_newline() {
+        let mut input = InputLine::new();
+        assert!(!input.push_data(b"inten"));
+        assert!(input.push_data(b"sity 10\nred"));
+        assert_eq!(input.to_str(), Ok("intensity 10\n"));
+
+        input.discard();
+        assert_eq!(input.length, 3);
+        assert!(input.push_data(b" 4\n"));
+        assert_eq!(input.to_str(), Ok("red 4\n"));
+    }
+}
@@ -0,0 +1,690 @@
+
+use crate::rgb::{ Colour, ColourSpace, EASED_MODE_COUNT, DEFAULT_EASINGS };
+use crate::curve::{ OutputCurve };
+use crate::easing::{ Easing };
+use crate::ir::{ IrFilter, IrProtocols, IR_FILTER_MAX };
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FlashError;
+
+// A region of flash made up of equally sized pages, addressed from the start of the region
+pub trait FlashDevice {
+    fn page_size(&self) -> usize;
+    fn num_pages(&self) -> usize;
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), FlashError>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+    fn erase(&mut self, page: usize) -> Result<(), FlashError>;
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub power: bool,
+    pub channel: u8,
+    pub index: u8,
+    pub intensity: u8,
+    pub delay: u32,
+    pub transition: u32,
+    pub power_transition: u32,
+    pub colour: Colour,
+    pub calibration: Colour,
+    pub curve: OutputCurve,
+    pub easings: [Easing; EASED_MODE_COUNT],
+    pub transition_easing: Easing,
+    pub fade_space: ColourSpace,
+    pub breathe_floor: u8,
+    pub flicker: u8,
+    pub sunrise_minutes: u8,
+    pub sunrise_alarm: Option<u16>,
+    pub ir_filter: IrFilter,
+    pub ir_protocols: IrProtocols,
+    pub ir_repeat_delay: u16,
+    pub ir_acceleration: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            power: true,
+            channel: 7,
+            index: 23,
+            intensity: 255,
+            delay: 5000,
+            transition: 0,
+            power_transition: 0,
+            colour: Colour::new(0xff, 0xff, 0xff),
+            calibration: Colour::new(0xff, 0xff, 0xff),
+            curve: OutputCurve::Linear,
+            easings: DEFAULT_EASINGS,
+            transition_easing: Easing::Linear,
+            fade_space: ColourSpace::Rgb,
+            breathe_floor: 32,
+            flicker: 128,
+            sunrise_minutes: 30,
+            sunrise_alarm: None,
+            ir_filter: IrFilter::default(),
+            ir_protocols: IrProtocols::ALL,
+            ir_repeat_delay: 300,
+            ir_acceleration: 4,
+        }
+    }
+}
+
+// Record layout (little endian):
+//   0  magic "RG"          14  delay (u32)
+//   2  format version      18  colour (r, g, b)
+//   3  flags (bit 0 power) 21  calibration (r, g, b)
+//   4  sequence (u32)      24  transition (u32)
+//   8  channel             28  power transition (u32)
+//   9  index               32  transition easing (from version 2)
+//  10  intensity           33  mode easings (8, in the order of EASED_MODES, from version 2)
+//  11  curve               41  fade colour space
+//  12  gamma (u16)         42  breathe floor (from version 3)
+//                          43  flicker (from version 4)
+//                          44  sunrise minutes (from version 5)
+//                          45  sunrise alarm (u16, minute of the day + 1, or 0 for none)
+//                          47  number of IR addresses
+//                          48  IR addresses (4)
+//                          52  IR protocols (bit for each, from version 6)
+//                          53  IR repeat delay (u16, from version 7)
+//                          55  IR acceleration (from version 7)
+//                          56  reserved (zero)
+//                          62  CRC-16/CCITT of bytes 0-61
+pub const RECORD_SIZE: usize = 64;
+
+const RECORD_MAGIC: [u8; 2] = *b"RG";
+// The version is increased when a setting is added that older records don't have a value for, so it can be
+// given its default when an older record is loaded
+const RECORD_VERSION: u8 = 7;
+
+impl Settings {
+    pub fn encode(&self, sequence: u32) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+
+        record[0..2].copy_from_slice(&RECORD_MAGIC);
+        record[2] = RECORD_VERSION;
+        record[3] = self.power as u8;
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        record[8] = self.channel;
+        record[9] = self.index;
+        record[10] = self.intensity;
+        let (curve, gamma) = match self.curve {
+            OutputCurve::Linear => (0, 0),
+            OutputCurve::Square => (1, 0),
+            OutputCurve::Cie1931 => (2, 0),
+            OutputCurve::Gamma(gamma) => (3, gamma),
+        };
+        record[11] = curve;
+        record[12..14].copy_from_slice(&gamma.to_le_bytes());
+        record[14..18].copy_from_slice(&self.delay.to_le_bytes());
+        record[18..21].copy_from_slice(&[self.colour.r, self.colour.g, self.colour.b]);
+        record[21..24].copy_from_slice(&[self.calibration.r, self.calibration.g, self.calibration.b]);
+        record[24..28].copy_from_slice(&self.transition.to_le_bytes());
+        record[28..32].copy_from_slice(&self.power_transition.to_le_bytes());
+        record[32] = self.transition_easing.to_u8();
+        for (byte, easing) in record[33..33 + EASED_MODE_COUNT].iter_mut().zip(self.easings.iter()) {
+            *byte = easing.to_u8();
+        }
+        record[41] = match self.fade_space {
+            ColourSpace::Rgb => 0,
+            ColourSpace::Hsv => 1,
+        };
+        record[42] = self.breathe_floor;
+        record[43] = self.flicker;
+        record[44] = self.sunrise_minutes;
+        let alarm = self.sunrise_alarm.map(|minute| minute + 1).unwrap_or(0);
+        record[45..47].copy_from_slice(&alarm.to_le_bytes());
+        let addresses = self.ir_filter.addresses();
+        record[47] = addresses.len() as u8;
+        record[48..48 + addresses.len()].copy_from_slice(addresses);
+        record[52] = self.ir_protocols.to_u8();
+        record[53..55].copy_from_slice(&self.ir_repeat_delay.to_le_bytes());
+        record[55] = self.ir_acceleration;
+
+        let crc = crc16(&record[0..RECORD_SIZE - 2]);
+        record[RECORD_SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    // Returns the settings and the sequence number if the record is valid
+    pub fn decode(record: &[u8]) -> Option<(Settings, u32)> {
+        if record.len() != RECORD_SIZE || record[0..2] != RECORD_MAGIC || !(1..=RECORD_VERSION).contains(&record[2]) {
+            return None;
+        }
+        let version = record[2];
+
+        let crc = u16::from_le_bytes([record[RECORD_SIZE - 2], record[RECORD_SIZE - 1]]);
+        if crc != crc16(&record[0..RECORD_SIZE - 2]) {
+            return None;
+        }
+
+        let gamma = u16::from_le_bytes([record[12], record[13]]);
+        let curve = match record[11] {
+            0 => OutputCurve::Linear,
+            1 => OutputCurve::Square,
+            2 => OutputCurve::Cie1931,
+            3 => OutputCurve::Gamma(gamma),
+            _ => return None,
+        };
+
+        let mut easings = [Easing::Linear; EASED_MODE_COUNT];
+        for (easing, byte) in easings.iter_mut().zip(record[33..33 + EASED_MODE_COUNT].iter()) {
+            *easing = Easing::from_u8(*byte)?;
+        }
+
+        let fade_space = match record[41] {
+            0 => ColourSpace::Rgb,
+            1 => ColourSpace::Hsv,
+            _ => return None,
+        };
+
+        let sunrise_alarm = match u16::from_le_bytes([record[45], record[46]]) {
+            0 => None,
+            alarm @ 1..=1440 => Some(alarm - 1),
+            _ => return None,
+        };
+        let ir_filter = match record[47] as usize {
+            len if len <= IR_FILTER_MAX => IrFilter::new(&record[48..48 + len]).ok()?,
+            _ => return None,
+        };
+
+        let mut settings = Settings {
+            power: record[3] & 0x01 != 0,
+            channel: record[8],
+            index: record[9],
+            intensity: record[10],
+            delay: u32::from_le_bytes([record[14], record[15], record[16], record[17]]),
+            transition: u32::from_le_bytes([record[24], record[25], record[26], record[27]]),
+            power_transition: u32::from_le_bytes([record[28], record[29], record[30], record[31]]),
+            colour: Colour::new(record[18], record[19], record[20]),
+            calibration: Colour::new(record[21], record[22], record[23]),
+            curve,
+            easings,
+            transition_easing: Easing::from_u8(record[32])?,
+            fade_space,
+            breathe_floor: record[42],
+            flicker: record[43],
+            sunrise_minutes: record[44],
+            sunrise_alarm,
+            ir_filter,
+            ir_protocols: IrProtocols::from_u8(record[52])?,
+            ir_repeat_delay: u16::from_le_bytes([record[53], record[54]]),
+            ir_acceleration: record[55],
+        };
+
+        // Settings added since an older record was saved have zeros in their place, which aren't always their
+        // defaults, so they're replaced
+        let defaults = Settings::default();
+        if version < 2 {
+            settings.easings = defaults.easings;
+            settings.transition_easing = defaults.transition_easing;
+        }
+        if version < 3 {
+            // The breathe mode was added along with its floor, so its easing is new as well
+            settings.breathe_floor = defaults.breathe_floor;
+            settings.easings[2] = defaults.easings[2];
+        }
+        if version < 4 {
+            settings.flicker = defaults.flicker;
+        }
+        if version < 5 {
+            settings.sunrise_minutes = defaults.sunrise_minutes;
+        }
+        if version < 6 {
+            settings.ir_protocols = defaults.ir_protocols;
+        }
+        if version < 7 {
+            settings.ir_repeat_delay = defaults.ir_repeat_delay;
+            settings.ir_acceleration = defaults.ir_acceleration;
+        }
+
+        let sequence = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        Some((settings, sequence))
+    }
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xffff, data)
+}
+
+fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+// Records are appended to the next free slot, wrapping around the pages given to the store, and the record
+// with the highest sequence number is the current one.  A page is only erased when the next record is about
+// to be written to it, so the previous records on the other pages remain if power is lost
+pub struct SettingsStore {
+    first_page: usize,
+    pages: usize,
+    next_slot: usize,
+    next_sequence: u32,
+}
+
+impl SettingsStore {
+    pub fn new(first_page: usize, pages: usize) -> Self {
+        SettingsStore {
+            first_page,
+            pages,
+            next_slot: 0,
+            next_sequence: 0,
+        }
+    }
+
+    pub fn load<F: FlashDevice>(&mut self, flash: &mut F) -> Option<Settings> {
+        let mut latest: Option<(Settings, u32)> = None;
+
+        for slot in 0..self.num_slots(flash) {
+            let mut record = [0; RECORD_SIZE];
+            if flash.read(self.slot_offset(flash, slot), &mut record).is_err() {
+                continue;
+            }
+
+            if let Some((settings, sequence)) = Settings::decode(&record) {
+                if latest.map(|(_, latest_sequence)| sequence > latest_sequence).unwrap_or(true) {
+                    latest = Some((settings, sequence));
+                    self.next_slot = (slot + 1) % self.num_slots(flash);
+                    self.next_sequence = sequence.wrapping_add(1);
+                }
+            }
+        }
+
+        latest.map(|(settings, _)| settings)
+    }
+
+    pub fn save<F: FlashDevice>(&mut self, flash: &mut F, settings: &Settings) -> Result<(), FlashError> {
+        let record = settings.encode(self.next_sequence);
+        let slots_per_page = flash.page_size() / RECORD_SIZE;
+
+        // Skip over any slots that weren't erased, such as one that was partially written when power was lost
+        for _ in 0..self.num_slots(flash) {
+            let slot = self.next_slot;
+            self.next_slot = (slot + 1) % self.num_slots(flash);
+
+            if slot.is_multiple_of(slots_per_page) {
+                flash.erase(self.first_page + slot / slots_per_page)?;
+            } else if !self.is_blank(flash, slot)? {
+                continue;
+            }
+
+            flash.write(self.slot_offset(flash, slot), &record)?;
+            self.next_sequence = self.next_sequence.wrapping_add(1);
+            return Ok(());
+        }
+        Err(FlashError)
+    }
+
+    pub fn clear<F: FlashDevice>(&mut self, flash: &mut F) -> Result<(), FlashError> {
+        for page in self.first_page..self.first_page + self.pages {
+            flash.erase(page)?;
+        }
+        self.next_slot = 0;
+        Ok(())
+    }
+
+    fn num_slots<F: FlashDevice>(&self, flash: &F) -> usize {
+        self.pages * (flash.page_size() / RECORD_SIZE)
+    }
+
+    fn slot_offset<F: FlashDevice>(&self, flash: &F, slot: usize) -> usize {
+        self.first_page * flash.page_size() + slot * RECORD_SIZE
+    }
+
+    fn is_blank<F: FlashDevice>(&self, flash: &mut F, slot: usize) -> Result<bool, FlashError> {
+        let mut record = [0; RECORD_SIZE];
+        flash.read(self.slot_offset(flash, slot), &mut record)?;
+        Ok(record.iter().all(|byte| *byte == 0xff))
+    }
+}
+
+
+// Blob header layout (little endian):
+//   0  magic "RB"          4  sequence (u32)
+//   2  kind                8  length (u16)
+//   3  reserved (zero)    10  CRC-16/CCITT of bytes 0-9 and the data
+pub const BLOB_HEADER_SIZE: usize = 12;
+
+const BLOB_MAGIC: [u8; 2] = *b"RB";
+
+// Larger blocks of data that change rarely, like palettes, are saved whole.  The pages are split into two copies,
+// and each save replaces the older copy, so the newer one remains if power is lost part way through a save
+pub struct BlobStore {
+    first_page: usize,
+    pages: usize,
+    kind: u8,
+    next_copy: usize,
+    next_sequence: u32,
+}
+
+impl BlobStore {
+    pub fn new(first_page: usize, pages: usize, kind: u8) -> Self {
+        BlobStore {
+            first_page,
+            pages,
+            kind,
+            next_copy: 0,
+            next_sequence: 0,
+        }
+    }
+
+    // The largest amount of data that can be saved
+    pub fn capacity<F: FlashDevice>(&self, flash: &F) -> usize {
+        (self.pages / 2) * flash.page_size() - BLOB_HEADER_SIZE
+    }
+
+    // Read the newest valid copy into the buffer, returning its length
+    pub fn load<F: FlashDevice>(&mut self, flash: &mut F, data: &mut [u8]) -> Option<usize> {
+        let mut latest: Option<(usize, u32, usize)> = None;
+
+        for copy in 0..2 {
+            if let Some((sequence, length)) = self.check_copy(flash, copy) {
+                if latest.map(|(_, latest_sequence, _)| sequence > latest_sequence).unwrap_or(true) {
+                    latest = Some((copy, sequence, length));
+                }
+            }
+        }
+
+        let (copy, sequence, length) = latest?;
+        if length > data.len() || flash.read(self.copy_offset(flash, copy) + BLOB_HEADER_SIZE, &mut data[..length]).is_err() {
+            return None;
+        }
+        self.next_copy = 1 - copy;
+        self.next_sequence = sequence.wrapping_add(1);
+        Some(length)
+    }
+
+    pub fn save<F: FlashDevice>(&mut self, flash: &mut F, data: &[u8]) -> Result<(), FlashError> {
+        if data.len() > self.capacity(flash) {
+            return Err(FlashError);
+        }
+
+        let copy = self.next_copy;
+        let pages_per_copy = self.pages / 2;
+        for page in 0..pages_per_copy {
+            flash.erase(self.first_page + copy * pages_per_copy + page)?;
+        }
+
+        let mut header = [0; BLOB_HEADER_SIZE];
+        header[0..2].copy_from_slice(&BLOB_MAGIC);
+        header[2] = self.kind;
+        header[4..8].copy_from_slice(&self.next_sequence.to_le_bytes());
+        header[8..10].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        let crc = crc16_update(crc16(&header[0..10]), data);
+        header[10..12].copy_from_slice(&crc.to_le_bytes());
+
+        // The flash is programmed in half-words, so an odd length is padded with an erased byte
+        let offset = self.copy_offset(flash, copy);
+        let even = data.len() & !1;
+        flash.write(offset + BLOB_HEADER_SIZE, &data[..even])?;
+        if even < data.len() {
+            flash.write(offset + BLOB_HEADER_SIZE + even, &[data[even], 0xff])?;
+        }
+        // The header is written last, so that a copy is only valid once all of its data has been written
+        flash.write(offset, &header)?;
+
+        self.next_copy = 1 - copy;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn clear<F: FlashDevice>(&mut self, flash: &mut F) -> Result<(), FlashError> {
+        for page in self.first_page..self.first_page + self.pages {
+            flash.erase(page)?;
+        }
+        self.next_copy = 0;
+        Ok(())
+    }
+
+    fn copy_offset<F: FlashDevice>(&self, flash: &F, copy: usize) -> usize {
+        (self.first_page + copy * (self.pages / 2)) * flash.page_size()
+    }
+
+    // Returns the sequence number and length of a copy if its header and data are valid
+    fn check_copy<F: FlashDevice>(&self, flash: &mut F, copy: usize) -> Option<(u32, usize)> {
+        let offset = self.copy_offset(flash, copy);
+        let mut header = [0; BLOB_HEADER_SIZE];
+        flash.read(offset, &mut header).ok()?;
+        if header[0..2] != BLOB_MAGIC || header[2] != self.kind {
+            return None;
+        }
+
+        let length = u16::from_le_bytes([header[8], header[9]]) as usize;
+        if length > self.capacity(flash) {
+            return None;
+        }
+
+        let mut crc = crc16(&header[0..10]);
+        let mut chunk = [0; 32];
+        let mut position = 0;
+        while position < length {
+            let size = chunk.len().min(length - position);
+            flash.read(offset + BLOB_HEADER_SIZE + position, &mut chunk[..size]).ok()?;
+            crc = crc16_update(crc, &chunk[..size]);
+            position += size;
+        }
+
+        if crc != u16::from_le_bytes([header[10], header[11]]) {
+            return None;
+        }
+        Some((u32::from_le_bytes([header[4], header[5], header[6], header[7]]), length))
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub struct MockFlash {
+        pub data: Vec<u8>,
+        pub erases: Vec<usize>,
+        page_size: usize,
+    }
+
+    impl MockFlash {
+        pub fn new(pages: usize) -> Self {
+            MockFlash::with_page_size(pages, 256)
+        }
+
+        pub fn with_page_size(pages: usize, page_size: usize) -> Self {
+            MockFlash { data: vec![0xff; pages * page_size], erases: vec![], page_size }
+        }
+    }
+
+    impl FlashDevice for MockFlash {
+        fn page_size(&self) -> usize { self.page_size }
+        fn num_pages(&self) -> usize { self.data.len() / self.page_size }
+
+        fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), FlashError> {
+            data.copy_from_slice(&self.data[offset..offset + data.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+            // Like the real flash, only erased locations can be programmed
+            if self.data[offset..offset + data.len()].iter().any(|byte| *byte != 0xff) {
+                return Err(FlashError);
+            }
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn erase(&mut self, page: usize) -> Result<(), FlashError> {
+            self.erases.push(page);
+            let page_size = self.page_size;
+            self.data[page * page_size..(page + 1) * page_size].iter_mut().for_each(|byte| *byte = 0xff);
+            Ok(())
+        }
+    }
+
+    fn settings_with_intensity(intensity: u8) -> Settings {
+        Settings { intensity, ..Settings::default() }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let settings = Settings {
+            power: false,
+            channel: 3,
+            index: 12,
+            intensity: 100,
+            delay: 123_456,
+            transition: 1500,
+            power_transition: 800,
+            colour: Colour::new(1, 2, 3),
+            calibration: Colour::new(255, 200, 150),
+            curve: OutputCurve::Gamma(250),
+            easings: [Easing::Sine; EASED_MODE_COUNT],
+            transition_easing: Easing::EaseOut,
+            fade_space: ColourSpace::Hsv,
+            breathe_floor: 10,
+            flicker: 200,
+            sunrise_minutes: 45,
+            sunrise_alarm: Some(390),
+            ir_filter: IrFilter::new(&[0, 0xbf]).unwrap(),
+            ir_protocols: IrProtocols::parse(&["nec", "rc6"]).unwrap(),
+            ir_repeat_delay: 500,
+            ir_acceleration: 9,
+        };
+        let record = settings.encode(42);
+        assert_eq!(Settings::decode(&record), Some((settings, 42)));
+    }
+
+    // Make a record that claims to be from another version of the format
+    fn record_with_version(settings: &Settings, version: u8) -> [u8; RECORD_SIZE] {
+        let mut record = settings.encode(7);
+        record[2] = version;
+        let crc = crc16(&record[0..RECORD_SIZE - 2]);
+        record[RECORD_SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn test_record_older_versions() {
+        let settings = Settings {
+            intensity: 100,
+            easings: [Easing::EaseIn; EASED_MODE_COUNT],
+            transition_easing: Easing::EaseOut,
+            breathe_floor: 0,
+            flicker: 0,
+            sunrise_minutes: 0,
+            ir_protocols: IrProtocols::NONE,
+            ir_repeat_delay: 0,
+            ir_acceleration: 0,
+            ..Settings::default()
+        };
+        let defaults = Settings::default();
+        assert_eq!(Settings::decode(&record_with_version(&settings, RECORD_VERSION)), Some((settings, 7)));
+        assert_eq!(Settings::decode(&record_with_version(&settings, 0)), None);
+        assert_eq!(Settings::decode(&record_with_version(&settings, RECORD_VERSION + 1)), None);
+
+        // Settings that didn't exist yet are given their defaults, even though zero is valid for some of them
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 1)).unwrap();
+        assert_eq!(decoded.intensity, 100);
+        assert_eq!((decoded.easings, decoded.transition_easing), (defaults.easings, defaults.transition_easing));
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 2)).unwrap();
+        assert_eq!(decoded.easings[..2], settings.easings[..2]);
+        assert_eq!((decoded.breathe_floor, decoded.easings[2]), (defaults.breathe_floor, defaults.easings[2]));
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 3)).unwrap();
+        assert_eq!((decoded.breathe_floor, decoded.flicker), (0, defaults.flicker));
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 4)).unwrap();
+        assert_eq!((decoded.flicker, decoded.sunrise_minutes), (0, defaults.sunrise_minutes));
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 5)).unwrap();
+        assert_eq!((decoded.sunrise_minutes, decoded.ir_protocols), (0, defaults.ir_protocols));
+        let (decoded, _) = Settings::decode(&record_with_version(&settings, 6)).unwrap();
+        assert_eq!(decoded.ir_protocols, IrProtocols::NONE);
+        assert_eq!((decoded.ir_repeat_delay, decoded.ir_acceleration), (defaults.ir_repeat_delay, defaults.ir_acceleration));
+    }
+
+    #[test]
+    fn test_record_rejects_corruption() {
+        let mut record = Settings::default().encode(1);
+        record[10] ^= 0x01;
+        assert_eq!(Settings::decode(&record), None);
+        assert_eq!(Settings::decode(&[0xff; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn test_store_empty() {
+        let mut flash = MockFlash::new(2);
+        let mut store = SettingsStore::new(0, 2);
+        assert_eq!(store.load(&mut flash), None);
+    }
+
+    #[test]
+    fn test_store_wear_levelling() {
+        let mut flash = MockFlash::new(3);
+        let mut store = SettingsStore::new(1, 2);
+        store.load(&mut flash);
+
+        // Two pages of four slots each, so this wraps around to the first page twice
+        for i in 0..20 {
+            store.save(&mut flash, &settings_with_intensity(i)).unwrap();
+        }
+        assert_eq!(flash.erases, vec![1, 2, 1, 2, 1]);
+        assert_eq!(store.load(&mut flash), Some(settings_with_intensity(19)));
+        assert!(flash.data[0..256].iter().all(|byte| *byte == 0xff));
+
+        let mut store = SettingsStore::new(1, 2);
+        assert_eq!(store.load(&mut flash), Some(settings_with_intensity(19)));
+        store.save(&mut flash, &settings_with_intensity(20)).unwrap();
+        assert_eq!(store.load(&mut flash), Some(settings_with_intensity(20)));
+    }
+
+    #[test]
+    fn test_store_skips_partial_record() {
+        let mut flash = MockFlash::new(2);
+        let mut store = SettingsStore::new(0, 2);
+        store.save(&mut flash, &settings_with_intensity(1)).unwrap();
+        flash.data[RECORD_SIZE] = 0x00;
+        store.save(&mut flash, &settings_with_intensity(2)).unwrap();
+        assert_eq!(store.load(&mut flash), Some(settings_with_intensity(2)));
+        assert_eq!(Settings::decode(&flash.data[RECORD_SIZE * 2..RECORD_SIZE * 3]), Some((settings_with_intensity(2), 1)));
+    }
+
+    #[test]
+    fn test_store_clear() {
+        let mut flash = MockFlash::new(2);
+        let mut store = SettingsStore::new(0, 2);
+        store.save(&mut flash, &settings_with_intensity(1)).unwrap();
+        store.clear(&mut flash).unwrap();
+        assert_eq!(store.load(&mut flash), None);
+    }
+
+    #[test]
+    fn test_blob_store() {
+        let mut flash = MockFlash::new(5);
+        let mut store = BlobStore::new(1, 4, 7);
+        let mut data = [0; 512];
+        assert_eq!(store.load(&mut flash, &mut data), None);
+        assert_eq!(store.capacity(&flash), 500);
+
+        let blob: Vec<u8> = (0..301).map(|i| i as u8).collect();
+        store.save(&mut flash, &blob).unwrap();
+        store.save(&mut flash, &blob[..10]).unwrap();
+        assert_eq!(flash.erases, vec![1, 2, 3, 4]);
+
+        let mut store = BlobStore::new(1, 4, 7);
+        assert_eq!(store.load(&mut flash, &mut data), Some(10));
+        assert_eq!(data[..10], blob[..10]);
+
+        // The next save replaces the older copy, and a copy that's corrupted falls back to the other one
+        store.save(&mut flash, &blob).unwrap();
+        assert_eq!(flash.erases, vec![1, 2, 3, 4, 1, 2]);
+        flash.data[256 + BLOB_HEADER_SIZE + 300] ^= 0x01;
+        assert_eq!(store.load(&mut flash, &mut data), Some(10));
+
+        assert_eq!(BlobStore::new(1, 4, 8).load(&mut flash, &mut data), None);
+        assert!(store.save(&mut flash, &[0; 501]).is_err());
+        store.clear(&mut flash).unwrap();
+        assert_eq!(store.load(&mut flash, &mut data), None);
+    }
+}
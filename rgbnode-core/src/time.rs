@@ -0,0 +1,117 @@
+
+use core::cell::Cell;
+
+
+pub trait Clock {
+    fn millis(&self) -> u32;
+}
+
+// A clock that only moves when told to, for tests and host simulation
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<u32>,
+}
+
+impl ManualClock {
+    pub fn new(now: u32) -> Self {
+        ManualClock {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u32) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, ms: u32) {
+        self.now.set(self.now.get().wrapping_add(ms));
+    }
+}
+
+impl Clock for ManualClock {
+    fn millis(&self) -> u32 {
+        self.now.get()
+    }
+}
+
+
+pub const DAY_MILLIS: u32 = 24 * 60 * 60 * 1000;
+
+// The time of day, counted from the millisecond clock since it was last set, because there's no real time clock
+// to keep it.  It has to be set again whenever the node is reset
+#[derive(Copy, Clone, Debug)]
+pub struct TimeOfDay {
+    base: u32,
+    set_at: u32,
+}
+
+impl TimeOfDay {
+    pub fn new(millis_of_day: u32, now: u32) -> Self {
+        TimeOfDay {
+            base: millis_of_day % DAY_MILLIS,
+            set_at: now,
+        }
+    }
+
+    // The milliseconds since midnight.  This needs to be called at least once every 49 days, so that the whole
+    // days can be moved into the base before the millisecond clock wraps around
+    pub fn millis(&mut self, now: u32) -> u32 {
+        let elapsed = now.wrapping_sub(self.set_at);
+        if elapsed >= DAY_MILLIS {
+            self.set_at = self.set_at.wrapping_add(elapsed / DAY_MILLIS * DAY_MILLIS);
+        }
+        (self.base + now.wrapping_sub(self.set_at)) % DAY_MILLIS
+    }
+
+    // Parse a time given as "hh:mm", returning the minutes since midnight
+    pub fn parse_minutes(time: &str) -> Option<u16> {
+        let (hours, minutes) = time.split_once(':')?;
+        let hours = lexical_core::parse::<u16>(hours.as_bytes()).ok()?;
+        let minutes = lexical_core::parse::<u16>(minutes.as_bytes()).ok()?;
+        if hours < 24 && minutes < 60 { Some(hours * 60 + minutes) } else { None }
+    }
+}
+
+// Whether a time of day comes after the earlier time and up to the later time, wrapping around at midnight
+pub fn time_passed(earlier: u32, later: u32, time: u32) -> bool {
+    let since = (later + DAY_MILLIS - time) % DAY_MILLIS;
+    let elapsed = (later + DAY_MILLIS - earlier) % DAY_MILLIS;
+    since < elapsed
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_of_day() {
+        let mut time = TimeOfDay::new(23 * 3_600_000, 1000);
+        assert_eq!(time.millis(1000), 23 * 3_600_000);
+        assert_eq!(time.millis(1000 + 3_600_000), 0);
+
+        // The base is moved along each day, so the time is still right after the clock wraps around
+        let mut now = 1000u32;
+        for _ in 0..60 {
+            now = now.wrapping_add(DAY_MILLIS);
+            assert_eq!(time.millis(now.wrapping_add(60_000)), 23 * 3_600_000 + 60_000);
+        }
+    }
+
+    #[test]
+    fn test_parse_minutes() {
+        assert_eq!(TimeOfDay::parse_minutes("07:30"), Some(450));
+        assert_eq!(TimeOfDay::parse_minutes("0:00"), Some(0));
+        assert_eq!(TimeOfDay::parse_minutes("24:00"), None);
+        assert_eq!(TimeOfDay::parse_minutes("7"), None);
+    }
+
+    #[test]
+    fn test_time_passed() {
+        assert!(time_passed(1000, 2000, 1500));
+        assert!(time_passed(1000, 2000, 2000));
+        assert!(!time_passed(1000, 2000, 1000));
+        assert!(time_passed(DAY_MILLIS - 1000, 1000, 0));
+        assert!(!time_passed(DAY_MILLIS - 1000, 1000, 5000));
+    }
+}
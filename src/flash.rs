@@ -0,0 +1,50 @@
+
+use stm32f1xx_hal::flash::{ Parts, FlashSize, SectorSize, SZ_1K };
+
+use rgbnode_core::settings::{ FlashDevice, FlashError };
+
+
+// The top of the flash is reserved for storing settings (see memory.x)
+const FLASH_SIZE: FlashSize = FlashSize::Sz64K;
+const STORAGE_OFFSET: u32 = 0xE000;
+const STORAGE_PAGES: usize = 8;
+const PAGE_SIZE: usize = SZ_1K as usize;
+
+pub struct Stm32Flash {
+    parts: Parts,
+}
+
+impl Stm32Flash {
+    pub fn new(parts: Parts) -> Self {
+        Stm32Flash {
+            parts,
+        }
+    }
+}
+
+impl FlashDevice for Stm32Flash {
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn num_pages(&self) -> usize {
+        STORAGE_PAGES
+    }
+
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), FlashError> {
+        let writer = self.parts.writer(SectorSize::Sz1K, FLASH_SIZE);
+        let bytes = writer.read(STORAGE_OFFSET + offset as u32, data.len()).map_err(|_| FlashError)?;
+        data.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        let mut writer = self.parts.writer(SectorSize::Sz1K, FLASH_SIZE);
+        writer.write(STORAGE_OFFSET + offset as u32, data).map_err(|_| FlashError)
+    }
+
+    fn erase(&mut self, page: usize) -> Result<(), FlashError> {
+        let mut writer = self.parts.writer(SectorSize::Sz1K, FLASH_SIZE);
+        writer.page_erase(STORAGE_OFFSET + (page * PAGE_SIZE) as u32).map_err(|_| FlashError)
+    }
+}
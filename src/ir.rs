@@ -1,8 +1,4 @@
 
-use core::cell::RefCell;
-use core::ops::DerefMut;
-use cortex_m::interrupt::{ Mutex };
-
 use stm32f1xx_hal::{
     stm32::{ interrupt, Interrupt, TIM2, NVIC },
     gpio::{ gpiob::PB8, Floating, Input },
@@ -10,23 +6,28 @@ use stm32f1xx_hal::{
 };
 
 use infrared::{
-    hal::{ PeriodicReceiver },
-    protocols::{ Nec },
+    hal::{ PeriodicReceiver4 },
+    protocols::{ Nec, NecSamsung, Rc5, Rc6 },
 };
 
+use rgbnode_core::ir::{ IrInterface, IrQueue, IrType, IrCode };
+
 
-type IrProtocol = Nec;
 type IrPin = PB8<Input<Floating>>;
 type IrTimer = CountDownTimer<TIM2>;
-type IrReceiver = PeriodicReceiver<IrProtocol, IrPin>;
+// All of the protocols are decoded at the same time, so any remote can be used without choosing it first
+type IrReceiver = PeriodicReceiver4<Nec, NecSamsung, Rc5, Rc6, IrPin>;
 
 
 pub const SAMPLERATE: u32 = 20_000;
 
-static IR_CODE: Mutex<RefCell<Option<IrCode>>> = Mutex::new(RefCell::new(None));
+// Codes are queued so they aren't lost while the main loop is busy (eg. printing over semihosting)
+static IR_QUEUE: IrQueue = IrQueue::new();
 
 static mut IR_TIMER: Option<CountDownTimer<TIM2>> = None;
 static mut IR_RECEIVER: Option<IrReceiver> = None;
+// RC5 and RC6 keep sending the same frame while a button is held, and flip the toggle bit on each new press
+static mut IR_LAST_TOGGLE: Option<(IrCode, bool)> = None;
 
 
 #[interrupt]
@@ -36,51 +37,47 @@ fn TIM2() {
 
     timer.clear_update_interrupt_flag();
 
-    if let Ok(Some(cmd)) = receiver.poll() {
-        cortex_m::interrupt::free(|cs| {
-            if !cmd.repeat {
-                let mut data = IR_CODE.borrow(cs).borrow_mut();
-                let ref mut code = *data.deref_mut();
-                *code = Some(IrCode { protocol: IrType::Nec, addr: cmd.addr, cmd: cmd.cmd });
-            }
-        });
-    }
-
-}
+    let received = match receiver.poll() {
+        Ok((Some(cmd), _, _, _)) => Some(IrCode { repeat: cmd.repeat, ..IrCode::new(IrType::Nec, cmd.addr, cmd.cmd) }),
+        Ok((_, Some(cmd), _, _)) => Some(IrCode { repeat: cmd.repeat, ..IrCode::new(IrType::Samsung, cmd.addr, cmd.cmd) }),
+        Ok((_, _, Some(cmd), _)) => Some(check_toggle(IrCode::new(IrType::Rc5, cmd.addr, cmd.cmd), cmd.toggle)),
+        Ok((_, _, _, Some(cmd))) => Some(check_toggle(IrCode::new(IrType::Rc6, cmd.addr, cmd.cmd), cmd.toggle)),
+        _ => None,
+    };
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum IrType {
-    Nec,
+    if let Some(received) = received {
+        IR_QUEUE.push(received);
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct IrCode {
-    pub protocol: IrType,
-    pub addr: u8,
-    pub cmd: u8,
+// Mark the code as a repeat if the toggle bit hasn't changed.  Only called from the interrupt handler
+fn check_toggle(code: IrCode, toggle: bool) -> IrCode {
+    let last = unsafe { IR_LAST_TOGGLE };
+    unsafe { IR_LAST_TOGGLE = Some((code, toggle)) };
+    IrCode { repeat: last == Some((code, toggle)), ..code }
 }
 
 pub struct IrDevice;
 
 impl IrDevice {
-    pub fn init(ir_pin: IrPin, ir_timer: IrTimer) {
-        let ir_receiver: IrReceiver = PeriodicReceiver::new(ir_pin, SAMPLERATE);
+    pub fn init(ir_pin: IrPin, ir_timer: IrTimer) -> Self {
+        let ir_receiver = IrReceiver::new(ir_pin, SAMPLERATE);
         unsafe {
             IR_RECEIVER = Some(ir_receiver);
             IR_TIMER = Some(ir_timer);
             NVIC::unmask(Interrupt::TIM2);
         }
+        IrDevice
     }
+}
 
-    pub fn poll() -> Option<IrCode> {
-        cortex_m::interrupt::free(|cs| {
-            let mut data = IR_CODE.borrow(cs).borrow_mut();
-            let ref mut code = *data.deref_mut();
+impl IrInterface for IrDevice {
+    fn poll(&mut self) -> Option<IrCode> {
+        IR_QUEUE.pop()
+    }
 
-            let result = code.clone();
-            *code = None;
-            result
-        })
+    fn overflows(&self) -> u32 {
+        IR_QUEUE.overflows()
     }
 }
 
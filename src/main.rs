@@ -21,13 +21,20 @@ use stm32f1xx_hal::{
 
 mod ir;
 mod rgb;
-mod node;
+mod flash;
 mod serial;
 
+use core::marker::PhantomData;
+
+use rgbnode_core::node::{ RgbNode, Platform };
+use rgbnode_core::ir::{ IrInterface };
+use rgbnode_core::serial::{ InputLine };
+use rgbnode_core::time::{ Clock };
+
 use ir::{ IrDevice };
 use rgb::{ Stm32Rgb };
-use node::{ RgbNode };
-use serial::{ SerialDevice, InputLine };
+use flash::{ Stm32Flash };
+use serial::{ SerialDevice };
 
 
 //// System Timer for millisecond counting ////
@@ -36,13 +43,34 @@ static mut ELAPSED_MS: u32 = 0u32;
 
 #[exception]
 fn SysTick() {
-    unsafe { ELAPSED_MS += 1; }
+    unsafe { ELAPSED_MS = ELAPSED_MS.wrapping_add(1); }
 }
 
 fn millis() -> u32 {
     return unsafe { ELAPSED_MS };
 }
 
+pub struct SysTickClock;
+
+impl Clock for SysTickClock {
+    fn millis(&self) -> u32 {
+        millis()
+    }
+}
+
+
+//// Device Types ////
+
+pub struct Bluepill<'a>(PhantomData<&'a ()>);
+
+impl<'a> Platform for Bluepill<'a> {
+    type Rgb = Stm32Rgb;
+    type Serial = SerialDevice<'a>;
+    type Clock = SysTickClock;
+    type Flash = Stm32Flash;
+    type Ir = IrDevice;
+}
+
 
 //// Main Setup ////
 
@@ -109,7 +137,7 @@ fn main() -> ! {
     let mut ir_timer = Timer::tim2(dp.TIM2, &clocks, &mut rcc.apb1).start_count_down(ir::SAMPLERATE.hz());
     ir_timer.listen(Event::Update);
 
-    IrDevice::init(ir_pin, ir_timer);
+    let ir = IrDevice::init(ir_pin, ir_timer);
 
 
     // Configure PWM
@@ -129,20 +157,25 @@ fn main() -> ! {
 
 
     // Create RgbNode object and run
-    let rgbnode = RgbNode::new(rgb, serial);
+    let rgbnode = RgbNode::<Bluepill>::new(rgb, serial, SysTickClock, Stm32Flash::new(flash), ir);
 
     mainloop(rgbnode);
 }
 
-fn mainloop(mut rgbnode: RgbNode) -> ! {
+fn mainloop(mut rgbnode: RgbNode<Bluepill>) -> ! {
     let mut input = InputLine::new();
 
-    rgbnode.engine.toggle(&mut rgbnode.rgb);
+    rgbnode.restore_settings();
     loop {
         rgbnode.process_input(&mut input);
         rgbnode.handle_animation();
+        rgbnode.handle_autosave();
+        rgbnode.handle_schedule();
 
-        if let Some(code) = IrDevice::poll() {
+        while let Some(code) = rgbnode.ir.poll() {
+            if !code.repeat {
+                hprintln!("IR: {:#x}", code.cmd).ok();
+            }
             rgbnode.process_ir_code(code);
         }
     }
@@ -3,6 +3,8 @@ use stm32f1xx_hal::usb::{ Peripheral, UsbBus };
 use usb_device::{ prelude::*, bus::UsbBusAllocator };
 use usbd_serial::{ SerialPort, USB_CLASS_CDC };
 
+use rgbnode_core::serial::{ SerialInterface, InputLine };
+
 
 pub struct SerialDevice<'a> {
     usb_dev: UsbDevice<'a, UsbBus<Peripheral>>,
@@ -26,7 +28,13 @@ impl<'a> SerialDevice<'a> {
         }
     }
 
-    pub fn poll_read(&mut self, input: &mut InputLine) -> bool {
+    fn poll(&mut self) -> bool {
+        !self.usb_dev.poll(&mut [&mut self.serial])
+    }
+}
+
+impl<'a> SerialInterface for SerialDevice<'a> {
+    fn poll_read(&mut self, input: &mut InputLine) -> bool {
         let mut buf = [0u8; 64];
 
         if self.poll() {
@@ -46,65 +54,7 @@ impl<'a> SerialDevice<'a> {
         return false;
     }
 
-    fn poll(&mut self) -> bool {
-        !self.usb_dev.poll(&mut [&mut self.serial])
-    }
-
-    pub fn write(&mut self, string: &[u8]) {
+    fn write(&mut self, string: &[u8]) {
         self.serial.write(string).ok();
     }
 }
-
-pub struct InputLine {
-    pub term: usize,
-    pub length: usize,
-    pub data: [u8; 128]
-}
-
-impl InputLine {
-    pub fn new() -> InputLine {
-        InputLine {
-            term: 0,
-            length: 0,
-            data: [0u8; 128]
-        }
-    }
-
-    pub fn push_data(&mut self, s: &[u8]) -> bool {
-        let start = self.length;
-
-        for ch in s {
-            self.data[self.length] = *ch;
-            self.length += 1;
-        }
-
-        for i in start..self.length {
-            if self.data[i] == '\n' as u8 {
-                self.term = i + 1;
-                return true;
-            }
-        }
-
-        return false;
-    }
-
-    pub fn clear(&mut self) {
-        self.term = 0;
-        self.length = 0;
-    }
-
-    pub fn discard(&mut self) {
-        let diff = self.length - self.term;
-        for i in 0..diff {
-            self.data[i] = self.data[self.term + i];
-        }
-
-        self.length = diff;
-        self.term = 0;
-    }
-
-    pub fn to_str(&self) -> Result<&str, core::str::Utf8Error> {
-        core::str::from_utf8(&self.data[0..self.term])
-    }
-}
-